@@ -15,6 +15,7 @@ fn main() {
     let mut opts = Options::new();
     opts.parsing_style(ParsingStyle::StopAtFirstFree);
     opts.optopt("a", "adapter", "Chat Adapter to use", "slack|cli");
+    opts.optopt("c", "config", "Load the bot from a config file", "bot.toml");
 
     let matches = match opts.parse(&args[1..]) {
         Ok(m) => m,
@@ -24,44 +25,62 @@ fn main() {
     let adapter_name = matches.opt_str("a").unwrap_or("cli".to_owned());
 
     let name = "chatbotbot";
-    let mut bot = Chatbot::new(name);
 
-    // Add adapter based on command line argument
-    match adapter_name.as_ref() {
-        "slack" => bot.add_adapter(SlackAdapter::new(name)),
-        "cli" => bot.add_adapter(CliAdapter::new(name)),
-        "irc" => {
-            let config = chatbot::adapter::IrcConfig {
-                nickname: Some(format!("{}", name)),
-                alt_nicks: Some(vec![format!("{}_", name), format!("{}__", name)]),
-                server: Some(format!("irc.mozilla.org")),
-                channels: Some(vec![format!("#chatbot")]),
-                .. Default::default()
-            };
-            bot.add_adapter(IrcAdapter::new(config, name))
+    let mut bot = match matches.opt_str("c") {
+        Some(path) => {
+            match chatbot::config::load(&path) {
+                Ok(bot) => bot,
+                Err(e) => {
+                    println!("Unable to load {}: {}", path, e);
+                    return;
+                }
+            }
         },
-        _ => panic!("Unexpected adapter name. Use 'cli' or 'slack'.")
+        None => {
+            let mut bot = Chatbot::new(name);
+
+            // Add adapter based on command line argument
+            match adapter_name.as_ref() {
+                "slack" => bot.add_adapter(SlackAdapter::new(name)),
+                "cli" => bot.add_adapter(CliAdapter::new(name)),
+                "irc" => {
+                    let config = chatbot::adapter::IrcConfig {
+                        nickname: Some(format!("{}", name)),
+                        alt_nicks: Some(vec![format!("{}_", name), format!("{}__", name)]),
+                        server: Some(format!("irc.mozilla.org")),
+                        channels: Some(vec![format!("#chatbot")]),
+                        .. Default::default()
+                    };
+                    bot.add_adapter(IrcAdapter::new(config, name))
+                },
+                _ => panic!("Unexpected adapter name. Use 'cli' or 'slack'.")
+            };
+
+            bot.enable_help();
+            bot
+        }
     };
 
-    let ping = handler!("PingHandler", r"ping", |_, _| { Some("pong".to_owned()) });
+    let ping = handler!("PingHandler", r"ping", |_, _| { Some("pong".to_owned()) },
+                        description: "Replies pong to ping");
 
-    let trout = handler!("TroutSlap", r"slap (?P<user>.+)", move |matches, _| {
+    let trout = handler!("TroutSlap", r"slap (?P<user>.+)", |matches, _| {
         match matches.name("user") {
             Some(user) => {
-                Some(format!("{} slaps {} around a bit with a large trout",
-                             name, user))
+                Some(format!("slaps {} around a bit with a large trout", user))
             },
             None => None
         }
-    });
+    }, description: "Slaps a user with a trout", usage: "slap <user>").emote();
 
     let echo = handler!("EchoHandler", r"echo (?P<msg>.+)", |matches, _| {
         matches.name("msg").map(|msg| { msg.to_owned() })
-    });
+    }, description: "Repeats a message", usage: "echo <message>");
 
     bot.add_handler(ping);
     bot.add_addressed_handler(trout);
     bot.add_handler(echo);
 
-    bot.run();
+    bot.catch_signals();
+    bot.run().unwrap();
 }
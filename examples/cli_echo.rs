@@ -14,5 +14,5 @@ fn main() {
     bot.add_handler(echo);
     bot.add_adapter(CliAdapter::new("bot"));
 
-    bot.run();
+    bot.run().unwrap();
 }
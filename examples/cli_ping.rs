@@ -12,5 +12,5 @@ fn main() {
     bot.add_addressed_handler(ping);
     bot.add_adapter(CliAdapter::new("bot"));
 
-    bot.run();
+    bot.run().unwrap();
 }
@@ -1,27 +1,34 @@
 use std::io::{self, Write};
 use std::sync::mpsc::Sender;
 use std::sync::mpsc::channel;
-use std::thread;
+use std::thread::{self, JoinHandle};
 
 use regex::Regex;
 
 use adapter::ChatAdapter;
 use message::AdapterMsg;
 use message::IncomingMessage;
+use message::Target;
 
 
 /// The CliAdapter reads lines from stdin and dispatches them as
-/// IncomingMessages to the chatbot.  Replies are printed to stdout. There is
-/// currently no extra configuration available to the CliAdapter.
+/// IncomingMessages to the chatbot.  Replies are printed to stdout. Lines
+/// starting with `/me ` are sent as actions, and actions from the bot are
+/// printed as `* text`. There is currently no extra configuration available to
+/// the CliAdapter.
 pub struct CliAdapter {
     address_regex: Regex,
+    tx_outgoing: Option<Sender<AdapterMsg>>,
+    outgoing_thread: Option<JoinHandle<()>>,
 }
 
 impl CliAdapter {
     /// create a new CliAdapter
     pub fn new(bot_name: &str) -> CliAdapter {
         CliAdapter {
-            address_regex: Regex::new(format!(r"^{}:", bot_name).as_str()).unwrap()
+            address_regex: Regex::new(format!(r"^{}:", bot_name).as_str()).unwrap(),
+            tx_outgoing: None,
+            outgoing_thread: None,
         }
     }
 }
@@ -46,6 +53,7 @@ impl ChatAdapter for CliAdapter {
 
         let (tx_outgoing, rx_outgoing) = channel();
         let name = self.get_name().to_owned();
+        self.tx_outgoing = Some(tx_outgoing.clone());
 
         // Read from stdin and send messages to the main loop
         thread::Builder::new().name("Chatbot CLI Reader".to_owned()).spawn(move || {
@@ -56,9 +64,16 @@ impl ChatAdapter for CliAdapter {
                         if len == 0 {
                             break;
                         }
-                        let msg = IncomingMessage::new(name.to_owned(), None, None, None,
-                            line[..(len-1)].to_owned(),
+                        let line = &line[..(len-1)];
+                        let (text, action) = if line.starts_with("/me ") {
+                            (&line[4..], true)
+                        } else {
+                            (line, false)
+                        };
+                        let mut msg = IncomingMessage::new(name.to_owned(), None, None, None,
+                            text.to_owned(),
                             tx_outgoing.to_owned());
+                        msg.set_action(action);
                         tx_incoming.send(msg).unwrap();
                     },
                     Err(e) => {
@@ -72,20 +87,55 @@ impl ChatAdapter for CliAdapter {
         }).ok().expect("failed to create stdio reader");
 
         // process messages from the main loop
-        thread::Builder::new().name("Chatbot CLI".to_owned()).spawn(move || {
+        let outgoing = thread::Builder::new().name("Chatbot CLI".to_owned()).spawn(move || {
             loop {
                 // TODO don't blindly unwrap
                 match rx_outgoing.recv().unwrap() {
-                    AdapterMsg::Outgoing(msg) => {
+                    // The terminal has only the one user, so private messages are printed too
+                    AdapterMsg::Outgoing(msg) | AdapterMsg::Private(msg) => {
+                        if msg.is_action() {
+                            io::stdout().write(b"* ").unwrap();
+                        }
                         io::stdout().write(msg.as_bytes()).unwrap();
                         io::stdout().write(b"\n").unwrap();
                         io::stdout().flush().unwrap();
                     },
-                    _ => break
+                    AdapterMsg::Proactive(target, text) => {
+                        let line = match target {
+                            Target::Channel(chan) => format!("[{}] {}\n", chan, text),
+                            Target::User(user) => format!("[@{}] {}\n", user, text),
+                        };
+                        io::stdout().write(line.as_bytes()).unwrap();
+                        io::stdout().flush().unwrap();
+                    },
+                    AdapterMsg::Join(_) | AdapterMsg::Part(_) => {
+                        println!("CliAdapter: there are no channels to join or part");
+                    },
+                    AdapterMsg::Shutdown => {
+                        io::stdout().flush().unwrap();
+                        break
+                    },
                 }
             }
         }).ok().expect("failed to create stdio <-> chatbot proxy");
 
+        self.outgoing_thread = Some(outgoing);
+    }
+
+    fn outgoing(&self) -> Option<Sender<AdapterMsg>> {
+        self.tx_outgoing.clone()
+    }
+
+    /// Flush any pending output and stop the stdout thread. The stdin reader is left blocked on
+    /// its read since there is no portable way to interrupt it.
+    fn shutdown(&mut self) {
+        if let Some(tx) = self.tx_outgoing.take() {
+            let _ = tx.send(AdapterMsg::Shutdown);
+        }
+
+        if let Some(outgoing) = self.outgoing_thread.take() {
+            let _ = outgoing.join();
+        }
     }
 }
 
@@ -0,0 +1,366 @@
+//! Logging in to the network's services so that the bot only joins channels once it is known by
+//! its registered nick.
+
+use std::time::{Duration, Instant};
+
+use irc::proto::command::{CapSubCommand, Command};
+use irc::proto::response::Response;
+use rustc_serialize::base64::{ToBase64, STANDARD};
+
+use super::IrcConfig;
+
+/// Most base64 bytes sent in one AUTHENTICATE message
+const SASL_CHUNK_BYTES: usize = 400;
+
+/// How long NickServ has to answer IDENTIFY before logging in counts as failed
+const IDENTIFY_TIMEOUT_SECS: u64 = 30;
+
+/// How the IRC adapter logs in to the network. If logging in fails, including NickServ not
+/// answering within 30 seconds, the reason is logged and channels are joined anyway. Those
+/// restricted to registered users will refuse the bot.
+#[derive(Clone, Debug, PartialEq)]
+pub enum IrcAuth {
+    /// Don't log in. Channels are joined as soon as the server finishes registration.
+    None,
+    /// Send `IDENTIFY <password>` to NickServ once the server finishes registration
+    NickServ {
+        password: String,
+    },
+    /// Log in with SASL PLAIN during capability negotiation, before registration finishes
+    Sasl {
+        account: String,
+        password: String,
+    },
+}
+
+/// Where logging in has got to after a message from the server
+#[derive(Debug, PartialEq)]
+pub enum Progress {
+    /// Still registering or logging in
+    Waiting,
+    /// Registered and logged in, so channels can be joined
+    Ready,
+    /// Logging in failed for the given reason. Channels can still be joined once registration
+    /// finishes, except those restricted to registered users.
+    Failed(String),
+}
+
+#[derive(Debug, PartialEq)]
+enum State {
+    /// Waiting for the server to acknowledge the SASL capability
+    Requested,
+    /// Waiting for the server to accept the SASL credentials
+    Authenticating,
+    /// Waiting for the server to finish registration
+    Registering,
+    /// Waiting for NickServ to accept the password, which was sent at the given time
+    Identifying(Instant),
+    /// Nothing left to do on this connection
+    Done,
+}
+
+/// Logging in on one connection. Feed it every command from the server with `handle` and send
+/// the commands it returns.
+pub struct Login {
+    auth: IrcAuth,
+    state: State,
+}
+
+impl Login {
+    pub fn new(auth: IrcAuth) -> Login {
+        Login {
+            auth: auth,
+            state: State::Registering,
+        }
+    }
+
+    /// The commands which open the connection, in place of the irc crate's `identify`. SASL needs
+    /// capability negotiation to stay open until it is done.
+    pub fn register(&mut self, config: &IrcConfig) -> Vec<Command> {
+        let mut commands = Vec::new();
+
+        match self.auth {
+            IrcAuth::Sasl { .. } => {
+                let sasl = Some("sasl".to_owned());
+                commands.push(Command::CAP(None, CapSubCommand::REQ, None, sasl));
+                self.state = State::Requested;
+            },
+            _ => commands.push(Command::CAP(None, CapSubCommand::END, None, None)),
+        }
+
+        if !config.password().is_empty() {
+            commands.push(Command::PASS(config.password().to_owned()));
+        }
+        commands.push(Command::NICK(config.nickname().to_owned()));
+        commands.push(Command::USER(config.username().to_owned(), "0".to_owned(),
+                                    config.real_name().to_owned()));
+        commands
+    }
+
+    /// When the login gives up on NickServ, if it is waiting for it. It only notices when the
+    /// next command arrives, so the caller should make sure one does after this.
+    pub fn deadline(&self) -> Option<Instant> {
+        match self.state {
+            State::Identifying(sent) => Some(sent + Duration::from_secs(IDENTIFY_TIMEOUT_SECS)),
+            _ => None,
+        }
+    }
+
+    /// Advance the login with `command`, which came from `source`. Returns the commands to send
+    /// in reply and whether the bot may now join channels.
+    pub fn handle(&mut self, command: &Command, source: Option<&str>)
+                  -> (Vec<Command>, Progress) {
+        self.handle_at(command, source, Instant::now())
+    }
+
+    /// `handle`, with `command` arriving at `now`
+    fn handle_at(&mut self, command: &Command, source: Option<&str>, now: Instant)
+                 -> (Vec<Command>, Progress) {
+        if self.deadline().map_or(false, |deadline| now >= deadline) {
+            self.state = State::Done;
+            let reason = format!("NickServ didn't answer within {}s", IDENTIFY_TIMEOUT_SECS);
+            return (Vec::new(), Progress::Failed(reason));
+        }
+
+        match (&self.state, command) {
+            (&State::Requested, &Command::CAP(_, CapSubCommand::ACK, ref caps, ref more)) => {
+                if has_sasl(caps) || has_sasl(more) {
+                    self.state = State::Authenticating;
+                    return (vec![Command::AUTHENTICATE("PLAIN".to_owned())], Progress::Waiting);
+                }
+                return self.fail("the server didn't acknowledge SASL");
+            },
+            (&State::Requested, &Command::CAP(_, CapSubCommand::NAK, _, _)) => {
+                return self.fail("the server doesn't support SASL");
+            },
+            // Servers which don't know CAP ignore it and finish registration without SASL
+            (&State::Requested, &Command::Response(Response::RPL_WELCOME, _, _)) |
+            (&State::Requested, &Command::Response(Response::RPL_ENDOFMOTD, _, _)) |
+            (&State::Requested, &Command::Response(Response::ERR_NOMOTD, _, _)) => {
+                self.state = State::Done;
+                let reason = "the server doesn't support capabilities".to_owned();
+                return (Vec::new(), Progress::Failed(reason));
+            },
+            (&State::Authenticating, &Command::AUTHENTICATE(ref data)) if data == "+" => {
+                return (self.sasl_credentials(), Progress::Waiting);
+            },
+            (&State::Authenticating, &Command::Response(Response::RPL_SASLSUCCESS, _, _)) => {
+                self.state = State::Registering;
+                let end = Command::CAP(None, CapSubCommand::END, None, None);
+                return (vec![end], Progress::Waiting);
+            },
+            (&State::Authenticating, &Command::Response(response, _, ref reason)) => {
+                match response {
+                    Response::ERR_SASLFAIL | Response::ERR_SASLTOOLONG |
+                    Response::ERR_SASLABORT | Response::ERR_NICKLOCKED => {
+                        let reason = reason.as_ref().map_or("SASL failed", |r| &r[..]).to_owned();
+                        return self.fail(&reason);
+                    },
+                    _ => (),
+                }
+            },
+            (&State::Registering, &Command::Response(Response::RPL_ENDOFMOTD, _, _)) |
+            (&State::Registering, &Command::Response(Response::ERR_NOMOTD, _, _)) => {
+                if let IrcAuth::NickServ { ref password } = self.auth {
+                    self.state = State::Identifying(now);
+                    let identify = Command::PRIVMSG("NickServ".to_owned(),
+                                                    format!("IDENTIFY {}", password));
+                    return (vec![identify], Progress::Waiting);
+                }
+
+                self.state = State::Done;
+                return (Vec::new(), Progress::Ready);
+            },
+            (&State::Identifying(_), &Command::Response(Response::RPL_LOGGEDIN, _, _)) => {
+                self.state = State::Done;
+                return (Vec::new(), Progress::Ready);
+            },
+            (&State::Identifying(_), &Command::NOTICE(_, ref text))
+                if source.map_or(false, |s| s.eq_ignore_ascii_case("NickServ")) => {
+                // Services which don't send RPL_LOGGEDIN still say how it went
+                let text = text.to_lowercase();
+                if text.contains("you are now identified") ||
+                   text.contains("you are now logged in") {
+                    self.state = State::Done;
+                    return (Vec::new(), Progress::Ready);
+                } else if text.contains("invalid password") ||
+                          text.contains("password incorrect") {
+                    self.state = State::Done;
+                    let reason = "NickServ rejected the password".to_owned();
+                    return (Vec::new(), Progress::Failed(reason));
+                }
+            },
+            _ => (),
+        }
+
+        (Vec::new(), Progress::Waiting)
+    }
+
+    /// Give up on SASL, letting registration finish without it
+    fn fail(&mut self, reason: &str) -> (Vec<Command>, Progress) {
+        self.state = State::Done;
+        let end = Command::CAP(None, CapSubCommand::END, None, None);
+        (vec![end], Progress::Failed(reason.to_owned()))
+    }
+
+    /// The SASL PLAIN credentials, split into AUTHENTICATE messages of at most
+    /// `SASL_CHUNK_BYTES`. A message of exactly that length is followed by `+` so the server knows
+    /// there is no more.
+    fn sasl_credentials(&self) -> Vec<Command> {
+        let (account, password) = match self.auth {
+            IrcAuth::Sasl { ref account, ref password } => (account, password),
+            _ => unreachable!("only SASL logins authenticate"),
+        };
+
+        let plain = format!("{}\0{}\0{}", account, account, password);
+        let encoded = plain.as_bytes().to_base64(STANDARD);
+        let mut commands = encoded.as_bytes().chunks(SASL_CHUNK_BYTES).map(|chunk| {
+            // base64 is ASCII, so any split is on a character boundary
+            Command::AUTHENTICATE(String::from_utf8(chunk.to_vec()).unwrap())
+        }).collect::<Vec<_>>();
+
+        if encoded.len() % SASL_CHUNK_BYTES == 0 {
+            commands.push(Command::AUTHENTICATE("+".to_owned()));
+        }
+        commands
+    }
+}
+
+/// Whether a CAP capability list includes SASL
+fn has_sasl(caps: &Option<String>) -> bool {
+    caps.as_ref().map_or(false, |caps| caps.split(' ').any(|cap| cap == "sasl"))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::{Duration, Instant};
+
+    use irc::proto::command::{CapSubCommand, Command};
+    use irc::proto::response::Response;
+
+    use super::{IrcAuth, Login, Progress, IDENTIFY_TIMEOUT_SECS};
+    use adapter::irc::IrcConfig;
+
+    fn response(response: Response) -> Command {
+        Command::Response(response, vec!["bot".to_owned()], None)
+    }
+
+    fn config() -> IrcConfig {
+        IrcConfig {
+            nickname: Some("bot".to_owned()),
+            .. Default::default()
+        }
+    }
+
+    #[test]
+    fn test_sasl() {
+        let mut login = Login::new(IrcAuth::Sasl {
+            account: "bot".to_owned(),
+            password: "secret".to_owned()
+        });
+
+        let register = login.register(&config());
+        assert_eq!(register[0],
+                   Command::CAP(None, CapSubCommand::REQ, None, Some("sasl".to_owned())));
+
+        let ack = Command::CAP(Some("*".to_owned()), CapSubCommand::ACK, None,
+                               Some("sasl".to_owned()));
+        assert_eq!(login.handle(&ack, None),
+                   (vec![Command::AUTHENTICATE("PLAIN".to_owned())], Progress::Waiting));
+
+        let plus = Command::AUTHENTICATE("+".to_owned());
+        assert_eq!(login.handle(&plus, None),
+                   (vec![Command::AUTHENTICATE("Ym90AGJvdABzZWNyZXQ=".to_owned())],
+                    Progress::Waiting));
+
+        // Channels can't be joined until registration finishes as well
+        let end = Command::CAP(None, CapSubCommand::END, None, None);
+        assert_eq!(login.handle(&response(Response::RPL_SASLSUCCESS), None),
+                   (vec![end], Progress::Waiting));
+        assert_eq!(login.handle(&response(Response::RPL_ENDOFMOTD), None),
+                   (vec![], Progress::Ready));
+    }
+
+    #[test]
+    fn test_sasl_failure() {
+        let mut login = Login::new(IrcAuth::Sasl {
+            account: "bot".to_owned(),
+            password: "x".repeat(400)
+        });
+        login.register(&config());
+
+        let ack = Command::CAP(Some("*".to_owned()), CapSubCommand::ACK, None,
+                               Some("multi-prefix sasl".to_owned()));
+        login.handle(&ack, None);
+
+        // Long credentials are sent in 400 byte pieces
+        let (pieces, _) = login.handle(&Command::AUTHENTICATE("+".to_owned()), None);
+        assert_eq!(pieces.len(), 2);
+
+        let (replies, progress) = login.handle(&response(Response::ERR_SASLFAIL), None);
+        assert_eq!(replies, vec![Command::CAP(None, CapSubCommand::END, None, None)]);
+        assert_eq!(progress, Progress::Failed("SASL failed".to_owned()));
+        assert_eq!(login.handle(&response(Response::RPL_ENDOFMOTD), None),
+                   (vec![], Progress::Waiting));
+    }
+
+    #[test]
+    fn test_nickserv() {
+        let mut login = Login::new(IrcAuth::NickServ { password: "secret".to_owned() });
+        let register = login.register(&config());
+        assert_eq!(register[0], Command::CAP(None, CapSubCommand::END, None, None));
+
+        let identify = Command::PRIVMSG("NickServ".to_owned(), "IDENTIFY secret".to_owned());
+        assert_eq!(login.handle(&response(Response::ERR_NOMOTD), None),
+                   (vec![identify], Progress::Waiting));
+
+        let notice = Command::NOTICE("bot".to_owned(), "Invalid password for bot.".to_owned());
+        assert_eq!(login.handle(&notice, Some("someone")), (vec![], Progress::Waiting));
+        assert_eq!(login.handle(&notice, Some("NickServ")),
+                   (vec![], Progress::Failed("NickServ rejected the password".to_owned())));
+    }
+
+    #[test]
+    fn test_nickserv_timeout() {
+        let mut login = Login::new(IrcAuth::NickServ { password: "secret".to_owned() });
+        login.register(&config());
+
+        let start = Instant::now();
+        login.handle_at(&response(Response::RPL_ENDOFMOTD), None, start);
+        let late = start + Duration::from_secs(IDENTIFY_TIMEOUT_SECS);
+        assert_eq!(login.deadline(), Some(late));
+
+        let ping = Command::PING("irc.example.com".to_owned(), None);
+        let soon = start + Duration::from_secs(IDENTIFY_TIMEOUT_SECS - 1);
+        assert_eq!(login.handle_at(&ping, None, soon), (vec![], Progress::Waiting));
+
+        // The bot stops waiting, and a late answer changes nothing
+        assert_eq!(login.handle_at(&ping, None, late),
+                   (vec![], Progress::Failed("NickServ didn't answer within 30s".to_owned())));
+        assert_eq!(login.deadline(), None);
+
+        let notice = Command::NOTICE("bot".to_owned(), "You are now identified.".to_owned());
+        assert_eq!(login.handle(&notice, Some("NickServ")), (vec![], Progress::Waiting));
+    }
+
+    #[test]
+    fn test_sasl_unanswered() {
+        let auth = IrcAuth::Sasl { account: "bot".to_owned(), password: "secret".to_owned() };
+
+        // A server without CAP goes ahead and registers
+        let mut login = Login::new(auth.clone());
+        login.register(&config());
+        let reason = "the server doesn't support capabilities".to_owned();
+        assert_eq!(login.handle(&response(Response::RPL_WELCOME), None),
+                   (vec![], Progress::Failed(reason)));
+
+        // One which acknowledges something else has to be told registration can go ahead
+        let mut login = Login::new(auth);
+        login.register(&config());
+        let ack = Command::CAP(Some("*".to_owned()), CapSubCommand::ACK, None,
+                               Some("multi-prefix".to_owned()));
+        assert_eq!(login.handle(&ack, None),
+                   (vec![Command::CAP(None, CapSubCommand::END, None, None)],
+                    Progress::Failed("the server didn't acknowledge SASL".to_owned())));
+    }
+}
@@ -0,0 +1,133 @@
+//! The channels the IRC adapter should be in. They are rejoined after reconnecting and, when the
+//! bot's brain is persistent, after restarting.
+
+use std::sync::Mutex;
+
+use brain::Namespace;
+
+/// Brain key holding the channels, separated by spaces
+const CHANNELS_KEY: &'static str = "channels";
+
+/// Brain key holding the configured channels the bot has left, which aren't joined again
+const LEFT_KEY: &'static str = "left";
+
+/// The channels to join, shared between the adapter's threads
+pub struct Channels {
+    configured: Vec<String>,
+    state: Mutex<State>,
+    brain: Option<Namespace>,
+}
+
+struct State {
+    joined: Vec<String>,
+    left: Vec<String>,
+}
+
+impl Channels {
+    /// The channels saved in `brain`, along with any of `configured` the bot hasn't left. Channels
+    /// added to the config later are joined too.
+    pub fn load(configured: Vec<String>, brain: Option<Namespace>) -> Channels {
+        let saved = |key: &str| -> Vec<String> {
+            brain.as_ref().and_then(|brain| brain.get(key)).map_or(Vec::new(), |saved| {
+                saved.split(' ').filter(|chan| !chan.is_empty()).map(|chan| chan.to_owned())
+                     .collect()
+            })
+        };
+        let mut joined = saved(CHANNELS_KEY);
+        let left = saved(LEFT_KEY);
+
+        for chan in &configured {
+            if !contains(&joined, chan) && !contains(&left, chan) {
+                joined.push(chan.to_owned());
+            }
+        }
+
+        Channels {
+            configured: configured,
+            state: Mutex::new(State { joined: joined, left: left }),
+            brain: brain,
+        }
+    }
+
+    pub fn list(&self) -> Vec<String> {
+        self.state.lock().unwrap().joined.clone()
+    }
+
+    /// Remember `channel`. Returns false if it was already known.
+    pub fn add(&self, channel: &str) -> bool {
+        let mut state = self.state.lock().unwrap();
+        if contains(&state.joined, channel) {
+            return false;
+        }
+
+        state.joined.push(channel.to_owned());
+        state.left.retain(|chan| !same_channel(chan, channel));
+        self.save(&state);
+        true
+    }
+
+    /// Forget `channel`, because the bot parted or was kicked. A configured channel stays
+    /// forgotten. Returns false if it wasn't known.
+    pub fn remove(&self, channel: &str) -> bool {
+        let mut state = self.state.lock().unwrap();
+        let before = state.joined.len();
+        state.joined.retain(|chan| !same_channel(chan, channel));
+
+        if state.joined.len() == before {
+            return false;
+        }
+
+        if contains(&self.configured, channel) && !contains(&state.left, channel) {
+            state.left.push(channel.to_owned());
+        }
+        self.save(&state);
+        true
+    }
+
+    fn save(&self, state: &State) {
+        if let Some(ref brain) = self.brain {
+            brain.set(CHANNELS_KEY, state.joined.join(" "));
+            brain.set(LEFT_KEY, state.left.join(" "));
+        }
+    }
+}
+
+/// Whether `channels` includes `channel`
+fn contains(channels: &[String], channel: &str) -> bool {
+    channels.iter().any(|chan| same_channel(chan, channel))
+}
+
+/// IRC channel names are case insensitive
+fn same_channel(a: &str, b: &str) -> bool {
+    a.to_lowercase() == b.to_lowercase()
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use brain::{MemoryBrain, Namespace};
+    use super::Channels;
+
+    #[test]
+    fn test_channels_persist() {
+        let brain = Namespace::adapter(Arc::new(MemoryBrain::new()), "irc");
+        let channels = Channels::load(vec!["#chatbot".to_owned()], Some(brain.clone()));
+
+        assert!(channels.add("#rust"));
+        assert!(!channels.add("#Rust"));
+        assert!(channels.remove("#CHATBOT"));
+        assert!(!channels.remove("#chatbot"));
+
+        // Channels left stay left, and channels added to the config are joined
+        let configured = vec!["#chatbot".to_owned(), "#new".to_owned()];
+        let reloaded = Channels::load(configured.clone(), Some(brain.clone()));
+        assert_eq!(reloaded.list(), vec!["#rust".to_owned(), "#new".to_owned()]);
+
+        // Until the bot is asked to join again
+        assert!(reloaded.add("#chatbot"));
+        let reloaded = Channels::load(configured, Some(brain));
+        assert_eq!(reloaded.list(),
+                   vec!["#rust".to_owned(), "#new".to_owned(), "#chatbot".to_owned()]);
+    }
+}
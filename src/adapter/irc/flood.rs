@@ -0,0 +1,171 @@
+//! Keeping replies within what an IRC server accepts: lines short enough not to be truncated, sent
+//! slowly enough not to be disconnected for flooding.
+
+use std::time::{Duration, Instant};
+
+/// Most bytes in a line sent by a server, including the trailing CRLF
+const MAX_LINE_BYTES: usize = 512;
+
+/// Longest username assumed when reserving room for the prefix the server adds to relayed
+/// messages. Most servers limit it to 10 characters including a leading `~`.
+const MAX_USER_BYTES: usize = 10;
+
+/// Longest hostname assumed when reserving room for the prefix
+const MAX_HOST_BYTES: usize = 63;
+
+/// How many lines the IRC adapter sends before it has to slow down, and how fast it sends after
+/// that
+#[derive(Clone, Debug, PartialEq)]
+pub struct FloodControl {
+    /// Lines which may be sent back to back after a quiet period
+    pub burst: u32,
+    /// Lines per second once the burst is used up
+    pub per_second: f64,
+}
+
+impl Default for FloodControl {
+    /// Five lines at once, then one every two seconds. This keeps clear of the limits on common
+    /// networks.
+    fn default() -> FloodControl {
+        FloodControl {
+            burst: 5,
+            per_second: 0.5,
+        }
+    }
+}
+
+/// A token bucket holding up to `burst` tokens and refilling at `per_second`. Each line sent
+/// takes one token.
+pub struct TokenBucket {
+    capacity: f64,
+    per_second: f64,
+    tokens: f64,
+    updated: Instant,
+}
+
+impl TokenBucket {
+    /// A full bucket. Panics if `burst` is zero or `per_second` isn't positive, since nothing
+    /// could ever be sent.
+    pub fn new(limits: &FloodControl) -> TokenBucket {
+        assert!(limits.burst > 0, "flood control burst must be at least 1");
+        assert!(limits.per_second > 0.0, "flood control rate must be positive");
+
+        TokenBucket {
+            capacity: limits.burst as f64,
+            per_second: limits.per_second,
+            tokens: limits.burst as f64,
+            updated: Instant::now(),
+        }
+    }
+
+    /// Take a token at `now` if there is one. Otherwise return how long until there will be.
+    pub fn take(&mut self, now: Instant) -> Result<(), Duration> {
+        if now > self.updated {
+            let elapsed = now - self.updated;
+            let secs = elapsed.as_secs() as f64 + elapsed.subsec_nanos() as f64 / 1e9;
+            self.tokens = (self.tokens + secs * self.per_second).min(self.capacity);
+            self.updated = now;
+        }
+
+        if self.tokens >= 1.0 {
+            self.tokens -= 1.0;
+            Ok(())
+        } else {
+            let wait = (1.0 - self.tokens) / self.per_second;
+            Err(Duration::new(wait as u64, (wait.fract() * 1e9) as u32))
+        }
+    }
+}
+
+/// Bytes of message text which fit in one PRIVMSG from `nick` to `target`, leaving room for the
+/// `:nick!user@host PRIVMSG target :` prefix the server adds when relaying it.
+pub fn max_text_bytes(nick: &str, target: &str) -> usize {
+    let prefix = 1 + nick.len() + 1 + MAX_USER_BYTES + 1 + MAX_HOST_BYTES + 1;
+    let command = "PRIVMSG ".len() + target.len() + " :".len();
+
+    MAX_LINE_BYTES.saturating_sub(prefix + command + "\r\n".len())
+}
+
+/// Split `text` into lines of at most `max_bytes`. Newlines always start a new line and blank
+/// lines are left out, since IRC has no way to send them. Long lines are broken at the last space
+/// which fits, or mid-word if there is none, but never inside a UTF-8 character.
+pub fn split_message(text: &str, max_bytes: usize) -> Vec<String> {
+    let mut lines = Vec::new();
+
+    for line in text.lines() {
+        let mut rest = line;
+
+        while rest.len() > max_bytes {
+            let mut cut = max_bytes;
+            while !rest.is_char_boundary(cut) {
+                cut -= 1;
+            }
+
+            if cut == 0 {
+                // Not even one character fits, so send it alone rather than nothing at all
+                cut = rest.char_indices().nth(1).map_or(rest.len(), |(i, _)| i);
+            }
+
+            match rest[..cut].rfind(' ') {
+                Some(space) if space > 0 => {
+                    lines.push(rest[..space].to_owned());
+                    rest = &rest[space + 1..];
+                },
+                _ => {
+                    lines.push(rest[..cut].to_owned());
+                    rest = &rest[cut..];
+                }
+            }
+        }
+
+        if !rest.trim().is_empty() {
+            lines.push(rest.to_owned());
+        }
+    }
+
+    lines
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::{Duration, Instant};
+
+    use super::{max_text_bytes, split_message, FloodControl, TokenBucket};
+
+    #[test]
+    fn test_split_message() {
+        assert_eq!(split_message("one\r\n\ntwo\n", 100), vec!["one", "two"]);
+        assert_eq!(split_message("the quick brown fox", 10), vec!["the quick", "brown fox"]);
+        assert_eq!(split_message("abcdefghij", 4), vec!["abcd", "efgh", "ij"]);
+
+        // "é" is two bytes and is never cut in half
+        assert_eq!(split_message("ééé", 3), vec!["é", "é", "é"]);
+        assert_eq!(split_message("é", 1), vec!["é"]);
+
+        let long = "word ".repeat(200);
+        let lines = split_message(&long, max_text_bytes("bot", "#chan"));
+        assert!(lines.len() > 1);
+        assert!(lines.iter().all(|line| line.len() <= max_text_bytes("bot", "#chan")));
+        assert_eq!(lines.join(" ").trim(), long.trim());
+    }
+
+    #[test]
+    fn test_token_bucket() {
+        let mut bucket = TokenBucket::new(&FloodControl { burst: 2, per_second: 1.0 });
+        let start = Instant::now();
+
+        assert_eq!(bucket.take(start), Ok(()));
+        assert_eq!(bucket.take(start), Ok(()));
+        assert_eq!(bucket.take(start), Err(Duration::from_secs(1)));
+
+        assert_eq!(bucket.take(start + Duration::from_millis(500)),
+                   Err(Duration::from_millis(500)));
+        assert_eq!(bucket.take(start + Duration::from_secs(1)), Ok(()));
+
+        // A long quiet period refills no more than the burst
+        let later = start + Duration::from_secs(60);
+        assert_eq!(bucket.take(later), Ok(()));
+        assert_eq!(bucket.take(later), Ok(()));
+        assert!(bucket.take(later).is_err());
+    }
+}
@@ -0,0 +1,1153 @@
+use std::cmp;
+use std::collections::VecDeque;
+use std::sync::{Arc, Mutex, RwLock};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::mpsc::{channel, Receiver, RecvTimeoutError, Sender};
+use std::thread::{self, JoinHandle};
+use std::time::{Duration, Instant};
+
+pub type IrcConfig = ::irc::client::data::Config;
+
+mod auth;
+pub use self::auth::IrcAuth;
+use self::auth::{Login, Progress};
+
+mod channels;
+use self::channels::Channels;
+
+mod flood;
+pub use self::flood::FloodControl;
+use self::flood::{max_text_bytes, split_message, TokenBucket};
+
+use futures::{Future, Stream};
+use irc::error::Error as IrcError;
+use irc::proto::command::Command;
+use irc::proto::response::Response;
+use irc::client::server::IrcServer;
+use irc::client::server::Server;
+use irc::client::server::utils::ServerExt;
+
+use regex::{self, Regex};
+
+use adapter::{strip_address, ChatAdapter};
+use brain::Namespace;
+use event::{Event, IncomingEvent};
+use message::IncomingMessage;
+use message::AdapterMsg;
+use message::Target;
+
+/// Delay before the first reconnect attempt. It doubles after each failed attempt.
+const RECONNECT_MIN_SECS: u64 = 1;
+
+/// Longest delay between reconnect attempts
+const RECONNECT_MAX_SECS: u64 = 300;
+
+/// Most outgoing commands held while disconnected or rate limited. The oldest are dropped beyond
+/// this.
+const MAX_QUEUED: usize = 1000;
+
+/// How long shutting down waits for the rate limit to let held lines out before dropping them
+const SHUTDOWN_FLUSH_SECS: u64 = 5;
+
+/// How often the outgoing thread checks whether a held message can be sent
+const OUTGOING_POLL_MS: u64 = 500;
+
+/// Marks the start and end of a CTCP message inside a PRIVMSG
+const CTCP_DELIM: char = '\x01';
+
+/// The server to connect to and how to log in to it
+#[derive(Clone)]
+struct Network {
+    /// Name of the adapter connected to the network
+    adapter: String,
+    /// Reported as the `server` of messages and events. The server's host when not set.
+    name: Option<String>,
+    config: IrcConfig,
+    auth: IrcAuth,
+    invites: InvitePolicy,
+}
+
+/// Which channels the IRC adapter joins when it is invited
+#[derive(Clone, Debug, PartialEq)]
+pub enum InvitePolicy {
+    /// Stay out of channels the bot is invited to
+    Ignore,
+    /// Join any channel the bot is invited to
+    Accept,
+    /// Join when invited by one of these nicks. Nicks only identify someone on networks which
+    /// make them be registered.
+    From(Vec<String>),
+}
+
+impl InvitePolicy {
+    /// Whether an invitation from `inviter` should be accepted
+    fn allows(&self, inviter: Option<&str>) -> bool {
+        match *self {
+            InvitePolicy::Ignore => false,
+            InvitePolicy::Accept => true,
+            InvitePolicy::From(ref nicks) => inviter.map_or(false, |inviter| {
+                nicks.iter().any(|nick| nick.to_lowercase() == inviter.to_lowercase())
+            }),
+        }
+    }
+}
+
+/// The current connection, if any. Messages are only sent on it once the bot has logged in.
+type Connection = Arc<Mutex<Option<IrcServer>>>;
+
+/// A connection waiting for NickServ, and when the login gives up on it
+type Identifying = Arc<Mutex<Option<(IrcServer, Instant)>>>;
+
+/// State shared between the adapter's threads
+#[derive(Clone)]
+struct Shared {
+    conn: Connection,
+    /// The connection from when it opens until it closes, so shutdown can QUIT it while the bot
+    /// is still logging in
+    open: Connection,
+    identifying: Identifying,
+    nick: SharedNick,
+    channels: Arc<Channels>,
+    stopping: Arc<AtomicBool>,
+}
+
+/// The nick the server has given the bot and a pattern for spotting it in messages
+struct Nick {
+    nick: String,
+    mention: Regex,
+}
+
+impl Nick {
+    fn new(nick: &str) -> Nick {
+        // `nick:`, `nick,`, `@nick` or just `nick`, at the start of a message or anywhere in it
+        let pattern = format!(r"(?i)(?:^|\s)@?{}(?:[:,.!?]|\s|$)", regex::quote(nick));
+
+        Nick {
+            nick: nick.to_owned(),
+            mention: Regex::new(&pattern).unwrap(),
+        }
+    }
+
+    /// Whether `name` is this nick. IRC nicks are case insensitive.
+    fn is(&self, name: &str) -> bool {
+        self.nick.to_lowercase() == name.to_lowercase()
+    }
+}
+
+/// The bot's current nick, shared between the adapter and its threads
+type SharedNick = Arc<RwLock<Nick>>;
+
+/// Track the bot's nick from the server's welcome and from NICK changes
+fn track_nick(nick: &SharedNick, source: Option<&str>, command: &Command) {
+    let assigned = match *command {
+        Command::Response(Response::RPL_WELCOME, ref args, _) => args.get(0),
+        Command::NICK(ref new) if source.map_or(false, |s| nick.read().unwrap().is(s)) => {
+            Some(new)
+        },
+        _ => None
+    };
+
+    if let Some(assigned) = assigned {
+        let mut nick = nick.write().unwrap();
+        if nick.nick != *assigned {
+            println!("IrcAdapter: now known as {}", assigned);
+            *nick = Nick::new(assigned);
+        }
+    }
+}
+
+/// Translate a membership or topic change from `source` into an Event
+fn to_event(command: &Command, source: Option<&str>) -> Option<Event> {
+    let user = match source {
+        Some(user) => user.to_owned(),
+        None => return None
+    };
+
+    Some(match *command {
+        Command::JOIN(ref chan, _, _) => Event::Join {
+            channel: chan.to_owned(),
+            user: user,
+        },
+        Command::PART(ref chan, ref reason) => Event::Part {
+            channel: chan.to_owned(),
+            user: user,
+            reason: reason.clone(),
+        },
+        Command::QUIT(ref reason) => Event::Quit {
+            user: user,
+            reason: reason.clone(),
+        },
+        Command::NICK(ref new) => Event::Nick {
+            old: user,
+            new: new.to_owned(),
+        },
+        Command::TOPIC(ref chan, Some(ref topic)) => Event::Topic {
+            channel: chan.to_owned(),
+            user: Some(user),
+            topic: topic.to_owned(),
+        },
+        Command::KICK(ref chan, ref kicked, ref reason) => Event::Kick {
+            channel: chan.to_owned(),
+            user: kicked.to_owned(),
+            by: Some(user),
+            reason: reason.clone(),
+        },
+        _ => return None
+    })
+}
+
+/// Connect your bot to IRC with the IrcAdapter
+///
+/// The bot is addressed by its current nick, which follows alternate nicks and NICK changes. It
+/// can be named at the start of a message (`nick: ping`, `nick, ping`, `@nick ping`) or anywhere
+/// in it (`hey nick ping`), and every message sent to it in a query window is addressed.
+///
+/// The adapter reconnects with exponential backoff when the connection drops, identifying and
+/// joining the configured channels again each time. Replies sent while disconnected are held and
+/// delivered once the bot has logged in and rejoined its channels.
+///
+/// Replies are split on newlines and wherever they would be too long for the server to relay
+/// whole. The lines are sent in a burst and then at a steady rate so the bot isn't disconnected
+/// for flooding; see [`with_flood_control`](#method.with_flood_control).
+///
+/// Each adapter connects to one network. Add an adapter for each network the bot should be on,
+/// giving each a different [`with_name`](#method.with_name).
+///
+/// The bot can log in with SASL PLAIN or by identifying with NickServ; see
+/// [`with_auth`](#method.with_auth). Channels are joined once logging in has succeeded or failed,
+/// so a failure only keeps the bot out of channels restricted to registered users.
+///
+/// The bot joins the configured channels, and remembers the channels it is in using the bot's
+/// [`Brain`](../brain/trait.Brain.html). Channels joined and left with `IncomingMessage::join`
+/// and `part`, joined when invited (see [`with_invites`](#method.with_invites)), or left by being
+/// kicked, stay that way across restarts when the brain is persistent. Channels added to the
+/// config later are joined as well.
+///
+/// JOIN, PART, QUIT, NICK, TOPIC and KICK are reported as [`Event`](../event/enum.Event.html)s
+/// when the bot has event handlers.
+///
+/// # Examples
+///
+/// ```rust
+/// use chatbot::Chatbot;
+/// use chatbot::adapter::FloodControl;
+/// use chatbot::adapter::IrcAdapter;
+/// use chatbot::adapter::IrcConfig;
+///
+/// let name = "mybot";
+/// let mut bot = Chatbot::new(name);
+///
+/// let irc = IrcAdapter::new(IrcConfig {
+///     nickname: Some(format!("{}", name)),
+///     alt_nicks: Some(vec![format!("{}_", name), format!("{}__", name)]),
+///     server: Some(format!("irc.mozilla.org")),
+///     channels: Some(vec![format!("#chatbot")]),
+///     .. Default::default()
+/// }, name).with_flood_control(FloodControl { burst: 4, per_second: 1.0 });
+///
+/// bot.add_adapter(irc);
+/// ```
+pub struct IrcAdapter {
+    network: Network,
+    address_regex: Regex,
+    nick: SharedNick,
+    tx_events: Option<Sender<IncomingEvent>>,
+    brain: Option<Namespace>,
+    flood: FloodControl,
+    tx_outgoing: Option<Sender<AdapterMsg>>,
+    outgoing_thread: Option<JoinHandle<()>>,
+    incoming_thread: Option<JoinHandle<()>>,
+}
+
+impl IrcAdapter {
+    /// An adapter for the server in `config`. If `config` has a `nick_password`, the bot
+    /// identifies with NickServ before joining channels.
+    pub fn new(mut config: IrcConfig, bot_name: &str) -> IrcAdapter {
+        let nick = Nick::new(config.nickname.as_ref().map_or(bot_name, |nick| &nick[..]));
+        let auth = match config.nick_password.take() {
+            Some(password) => IrcAuth::NickServ { password: password },
+            None => IrcAuth::None,
+        };
+
+        IrcAdapter {
+            network: Network {
+                adapter: "IrcAdapter".to_owned(),
+                name: None,
+                config: config,
+                auth: auth,
+                invites: InvitePolicy::Ignore,
+            },
+            address_regex: nick.mention.clone(),
+            nick: Arc::new(RwLock::new(nick)),
+            tx_events: None,
+            brain: None,
+            flood: FloodControl::default(),
+            tx_outgoing: None,
+            outgoing_thread: None,
+            incoming_thread: None,
+        }
+    }
+
+    /// Change how fast replies are sent. See [`FloodControl`](struct.FloodControl.html) for the
+    /// default.
+    ///
+    /// Panics if `flood.burst` is zero or `flood.per_second` isn't positive.
+    pub fn with_flood_control(mut self, flood: FloodControl) -> IrcAdapter {
+        // Check the limits now rather than when the outgoing thread starts
+        TokenBucket::new(&flood);
+        self.flood = flood;
+        self
+    }
+
+    /// Name the adapter and the network it connects to, which is `IrcAdapter` and the server's
+    /// host by default. Messages from the network carry `name` as their `server`, and
+    /// [`BotHandle::send`](../struct.BotHandle.html#method.send) sends to the network by `name`.
+    ///
+    /// Adapters are told apart by name, so give each one a different name when the bot is on
+    /// more than one network.
+    pub fn with_name(mut self, name: &str) -> IrcAdapter {
+        self.network.adapter = name.to_owned();
+        self.network.name = Some(name.to_owned());
+        self
+    }
+
+    /// Change how the bot logs in, replacing any `nick_password` from the config. Channels are
+    /// only joined once logging in is over, so channels restricted to registered users work when
+    /// it succeeds.
+    pub fn with_auth(mut self, auth: IrcAuth) -> IrcAdapter {
+        self.network.auth = auth;
+        self
+    }
+
+    /// Choose which invitations to accept. Invitations are ignored by default.
+    pub fn with_invites(mut self, invites: InvitePolicy) -> IrcAdapter {
+        self.network.invites = invites;
+        self
+    }
+}
+
+/// Split a PRIVMSG into its text and whether it was a CTCP ACTION (`/me`). Other CTCP requests
+/// like VERSION aren't chat, so they give `None`.
+fn parse_privmsg(msg: &str) -> Option<(&str, bool)> {
+    if !msg.starts_with(CTCP_DELIM) {
+        return Some((msg, false));
+    }
+
+    let ctcp = msg.trim_matches(CTCP_DELIM);
+    if ctcp.starts_with("ACTION ") {
+        Some((&ctcp["ACTION ".len()..], true))
+    } else {
+        None
+    }
+}
+
+/// Connect to the server and send the registration messages. The irc crate is given no channels
+/// or NickServ password, since `login` decides when it is safe to join.
+fn connect(config: &IrcConfig, login: &mut Login) -> Result<IrcServer, IrcError> {
+    let server = try!(IrcServer::from_config(IrcConfig {
+        channels: None,
+        nick_password: None,
+        .. config.clone()
+    }));
+
+    for command in login.register(config) {
+        try!(server.send(command));
+    }
+    Ok(server)
+}
+
+/// Join `channels`, with the keys from the config
+fn join_channels(server: &IrcServer, config: &IrcConfig, channels: &Channels)
+                 -> Result<(), IrcError> {
+    for chan in channels.list() {
+        match config.channel_key(&chan) {
+            Some(key) => try!(server.send_join_with_keys(&chan, key)),
+            None => try!(server.send_join(&chan)),
+        }
+    }
+    Ok(())
+}
+
+/// Sleep for `delay`, waking early if the adapter is stopping
+fn backoff(delay: Duration, stopping: &AtomicBool) {
+    let step = Duration::from_millis(100);
+    let mut slept = Duration::from_millis(0);
+
+    while slept < delay && !stopping.load(Ordering::SeqCst) {
+        thread::sleep(step);
+        slept += step;
+    }
+}
+
+/// Keep a connection to the server open until the adapter stops, passing PRIVMSGs and events to
+/// the bot
+fn maintain_connection(network: Network, shared: Shared, tx_incoming: Sender<IncomingMessage>,
+                       tx_events: Option<Sender<IncomingEvent>>,
+                       tx_outgoing: Sender<AdapterMsg>) {
+    let Shared { conn, open, identifying, nick, channels, stopping } = shared;
+    let config = network.config;
+    let host = config.server().to_owned();
+    let adapter = network.adapter;
+    let name = network.name.unwrap_or(host.clone());
+    let invites = network.invites;
+    let mut delay = Duration::from_secs(RECONNECT_MIN_SECS);
+
+    while !stopping.load(Ordering::SeqCst) {
+        let mut login = Login::new(network.auth.clone());
+        let server = match connect(&config, &mut login) {
+            Ok(server) => server,
+            Err(e) => {
+                println!("IrcAdapter: failed to connect to {}: {}; retrying in {}s",
+                         host, e, delay.as_secs());
+                backoff(delay, &stopping);
+                delay = cmp::min(delay * 2, Duration::from_secs(RECONNECT_MAX_SECS));
+                continue;
+            }
+        };
+
+        println!("IrcAdapter: connected to {}", host);
+
+        // Shutdown sends QUIT on the open connection, so one which opened while the adapter was
+        // stopping is closed here instead
+        *open.lock().unwrap() = Some(server.clone());
+        if stopping.load(Ordering::SeqCst) {
+            let _ = server.send_quit("");
+        }
+
+        // Held messages wait until the server has finished registration and the bot has logged
+        // in and joined its channels, since the server refuses them before then
+        let mut registered = false;
+        let mut logged_in = false;
+        let mut published = false;
+
+        let res = server.stream().for_each(|message| {
+            track_nick(&nick, message.source_nickname(), &message.command);
+
+            let (replies, progress) = login.handle(&message.command, message.source_nickname());
+            for reply in replies {
+                try!(server.send(reply));
+            }
+            match progress {
+                Progress::Ready => logged_in = true,
+                Progress::Failed(reason) => {
+                    println!("IrcAdapter: failed to log in to {}: {}; joining channels anyway",
+                             host, reason);
+                    logged_in = true;
+                },
+                Progress::Waiting => (),
+            }
+
+            // The outgoing thread wakes this one once NickServ has had long enough to answer
+            let deadline = login.deadline();
+            let mut waiting = identifying.lock().unwrap();
+            if deadline.is_some() != waiting.is_some() {
+                *waiting = deadline.map(|deadline| (server.clone(), deadline));
+            }
+            drop(waiting);
+
+            match message.command {
+                Command::Response(Response::RPL_ENDOFMOTD, _, _) |
+                Command::Response(Response::ERR_NOMOTD, _, _) => registered = true,
+                _ => (),
+            }
+            if registered && logged_in && !published {
+                try!(join_channels(&server, &config, &channels));
+                *conn.lock().unwrap() = Some(server.clone());
+                published = true;
+                // Only a server which lets the bot all the way in resets the wait, so one which
+                // drops it straight after connecting isn't redialled every second
+                delay = Duration::from_secs(RECONNECT_MIN_SECS);
+            }
+
+            if let Some(ref tx_events) = tx_events {
+                if let Some(event) = to_event(&message.command, message.source_nickname()) {
+                    let incoming = IncomingEvent::new(adapter.clone(), Some(name.clone()),
+                                                      event, tx_outgoing.clone());
+
+                    if let Err(e) = tx_events.send(incoming) {
+                        println!("IrcAdapter: chatbot not receiving events: {}", e);
+                    }
+                }
+            }
+
+            let user = message.source_nickname().map(|user| user.to_owned());
+            match message.command {
+                Command::PRIVMSG(ref chan, ref msg) => {
+                    let (text, action) = match parse_privmsg(msg) {
+                        Some(parsed) => parsed,
+                        None => return Ok(()),
+                    };
+                    let mut incoming = IncomingMessage::new(adapter.clone(),
+                        Some(name.clone()), Some(chan.to_owned()), user, text.to_owned(),
+                        tx_outgoing.clone());
+                    incoming.set_action(action);
+
+                    if let Err(e) = tx_incoming.send(incoming) {
+                        println!("IrcAdapter: chatbot not receiving messages: {}", e);
+                    }
+                },
+                Command::INVITE(ref invited, ref chan) if nick.read().unwrap().is(invited) => {
+                    if invites.allows(message.source_nickname()) {
+                        println!("IrcAdapter: invited to {} by {:?}", chan, user);
+                        // Joined from the outgoing thread, within the flood limits
+                        if let Err(e) = tx_outgoing.send(AdapterMsg::Join(chan.to_owned())) {
+                            println!("IrcAdapter: outgoing thread gone; not joining: {}", e);
+                        }
+                    } else {
+                        println!("IrcAdapter: ignoring invite to {} from {:?}", chan, user);
+                    }
+                },
+                Command::KICK(ref chan, ref kicked, _) if nick.read().unwrap().is(kicked) => {
+                    println!("IrcAdapter: kicked from {} by {:?}", chan, user);
+                    channels.remove(chan);
+                },
+                _ => ()
+            }
+
+            Ok(())
+        }).wait();
+
+        *conn.lock().unwrap() = None;
+        *open.lock().unwrap() = None;
+        *identifying.lock().unwrap() = None;
+
+        if stopping.load(Ordering::SeqCst) {
+            break;
+        }
+
+        match res {
+            Ok(()) => {
+                println!("IrcAdapter: disconnected from {}; reconnecting in {}s", host,
+                         delay.as_secs());
+            },
+            Err(e) => {
+                println!("IrcAdapter: lost connection to {}: {}; reconnecting in {}s", host, e,
+                         delay.as_secs());
+            },
+        }
+        backoff(delay, &stopping);
+        delay = cmp::min(delay * 2, Duration::from_secs(RECONNECT_MAX_SECS));
+    }
+
+    println!("IrcAdapter: connection to {} closed", host);
+}
+
+/// Work out where a message from the bot should go on IRC, what it says, and whether it is an
+/// action
+fn destination(msg: AdapterMsg, nick: &Nick) -> Option<(String, String, bool)> {
+    match msg {
+        AdapterMsg::Outgoing(m) => {
+            let incoming = m.get_incoming();
+            // Messages sent directly to the bot arrive with the bot's nick as the channel
+            let to = match (incoming.channel(), incoming.user()) {
+                (Some(chan), Some(user)) if nick.is(chan) => user,
+                (Some(chan), _) => chan,
+                (None, _) => return None,
+            };
+            Some((to.to_owned(), m.as_ref().to_owned(), m.is_action()))
+        },
+        AdapterMsg::Private(m) => {
+            let text = m.as_ref().to_owned();
+            m.get_incoming().user().map(|user| (user.to_owned(), text, m.is_action()))
+        },
+        AdapterMsg::Proactive(target, text) => {
+            let to = match target {
+                Target::Channel(chan) => chan,
+                Target::User(user) => user,
+            };
+            Some((to, text, false))
+        },
+        // Control messages are handled by `deliver`
+        AdapterMsg::Join(_) | AdapterMsg::Part(_) | AdapterMsg::Shutdown => None,
+    }
+}
+
+/// Add `command` to the queue, dropping the oldest held commands if there are too many
+fn hold(queue: &mut VecDeque<Command>, command: Command) {
+    queue.push_back(command);
+
+    if queue.len() > MAX_QUEUED {
+        let dropped = queue.len() - MAX_QUEUED;
+        queue.drain(..dropped);
+        println!("IrcAdapter: too many undelivered commands; dropped the oldest {}", dropped);
+    }
+}
+
+/// Split `text` into lines short enough for the server to relay whole, encoding actions as CTCP,
+/// and add them to the queue
+fn hold_text(queue: &mut VecDeque<Command>, to: String, text: &str, action: bool, nick: &Nick) {
+    let mut max_bytes = max_text_bytes(&nick.nick, &to);
+    if action {
+        max_bytes = max_bytes.saturating_sub("\x01ACTION \x01".len());
+    }
+
+    for line in split_message(text, max_bytes) {
+        let line = if action {
+            format!("{}ACTION {}{}", CTCP_DELIM, line, CTCP_DELIM)
+        } else {
+            line
+        };
+        hold(queue, Command::PRIVMSG(to.clone(), line));
+    }
+}
+
+/// Send held commands in order as fast as `bucket` allows. Returns how long until the next one
+/// may be sent if some are still held because of the rate limit, or `None` if there's nothing
+/// more to do until a message arrives or the connection comes back.
+fn flush(conn: &Connection, queue: &mut VecDeque<Command>,
+         bucket: &mut TokenBucket) -> Option<Duration> {
+    let conn = conn.lock().unwrap();
+    let server = match *conn {
+        Some(ref server) => server,
+        None => return None
+    };
+
+    while let Some(command) = queue.pop_front() {
+        if let Err(wait) = bucket.take(Instant::now()) {
+            queue.push_front(command);
+            return Some(wait);
+        }
+
+        if let Err(e) = server.send(command.clone()) {
+            println!("IrcAdapter: failed to send: {}; holding until reconnected", e);
+            queue.push_front(command);
+            return None;
+        }
+    }
+
+    None
+}
+
+/// Send a PING to a connection whose login has waited long enough for NickServ, so the reply
+/// arrives to tell it to stop waiting
+fn wake_identifying(identifying: &Identifying) {
+    let mut identifying = identifying.lock().unwrap();
+    if identifying.as_ref().map_or(false, |&(_, deadline)| Instant::now() >= deadline) {
+        if let Some((server, _)) = identifying.take() {
+            let _ = server.send(Command::PING(server.config().server().to_owned(), None));
+        }
+    }
+}
+
+/// Deliver messages from the bot, holding them while the connection is down
+fn deliver(rx_outgoing: Receiver<AdapterMsg>, shared: Shared, flood: FloodControl) {
+    let Shared { conn, open, identifying, nick, channels, stopping } = shared;
+    let mut queue = VecDeque::new();
+    let mut bucket = TokenBucket::new(&flood);
+    let mut wait = None;
+
+    loop {
+        let poll = Duration::from_millis(OUTGOING_POLL_MS);
+        match rx_outgoing.recv_timeout(wait.map_or(poll, |wait| cmp::min(wait, poll))) {
+            Ok(AdapterMsg::Shutdown) => {
+                stopping.store(true, Ordering::SeqCst);
+                let deadline = Instant::now() + Duration::from_secs(SHUTDOWN_FLUSH_SECS);
+                while let Some(wait) = flush(&conn, &mut queue, &mut bucket) {
+                    if Instant::now() + wait > deadline {
+                        break;
+                    }
+                    thread::sleep(wait);
+                }
+                // Closes the connection even if the bot hasn't logged in yet
+                if let Some(ref server) = *open.lock().unwrap() {
+                    if let Err(e) = server.send_quit("") {
+                        println!("IrcAdapter: failed to send QUIT: {}", e);
+                    }
+                }
+                if !queue.is_empty() {
+                    println!("IrcAdapter: dropping {} undelivered commands", queue.len());
+                }
+                break
+            },
+            Ok(AdapterMsg::Join(chan)) => {
+                channels.add(&chan);
+                hold(&mut queue, Command::JOIN(chan, None, None));
+            },
+            Ok(AdapterMsg::Part(chan)) => {
+                channels.remove(&chan);
+                hold(&mut queue, Command::PART(chan, None));
+            },
+            Ok(msg) => {
+                let nick = nick.read().unwrap();
+                match destination(msg, &nick) {
+                    Some((to, text, action)) => hold_text(&mut queue, to, &text, action, &nick),
+                    None => println!("IrcAdapter: don't know where to send message"),
+                }
+            },
+            Err(RecvTimeoutError::Timeout) => (),
+            Err(RecvTimeoutError::Disconnected) => {
+                println!("IrcAdapter: outgoing channel closed");
+                stopping.store(true, Ordering::SeqCst);
+                break
+            }
+        }
+
+        wake_identifying(&identifying);
+        wait = flush(&conn, &mut queue, &mut bucket);
+    }
+}
+
+impl ChatAdapter for IrcAdapter {
+    fn get_name(&self) -> &str {
+        self.network.adapter.as_ref()
+    }
+
+    /// Matches the nick the bot was configured with. Addressing follows the bot's current nick
+    /// through `address`.
+    fn addresser(&self) -> &Regex {
+        &self.address_regex
+    }
+
+    fn address(&self, msg: &IncomingMessage) -> Option<String> {
+        let nick = self.nick.read().unwrap();
+        let contents = msg.get_contents();
+
+        match strip_address(&nick.mention, contents) {
+            Some(stripped) => Some(stripped),
+            // Everything said in a query window is meant for the bot
+            None if msg.channel().map_or(false, |chan| nick.is(chan)) => Some(contents.to_owned()),
+            None => None
+        }
+    }
+
+    fn set_event_sender(&mut self, tx_events: Sender<IncomingEvent>) {
+        self.tx_events = Some(tx_events);
+    }
+
+    /// Keeps the channels the bot is in
+    fn set_brain(&mut self, brain: Namespace) {
+        self.brain = Some(brain);
+    }
+
+    fn process_events(&mut self, tx_incoming: Sender<IncomingMessage>) {
+        let (tx_outgoing, rx_outgoing) = channel();
+        let configured = self.network.config.channels.clone().unwrap_or_default();
+        let shared = Shared {
+            conn: Arc::new(Mutex::new(None)),
+            open: Arc::new(Mutex::new(None)),
+            identifying: Arc::new(Mutex::new(None)),
+            nick: self.nick.clone(),
+            channels: Arc::new(Channels::load(configured, self.brain.take())),
+            stopping: Arc::new(AtomicBool::new(false)),
+        };
+        self.tx_outgoing = Some(tx_outgoing.clone());
+
+        let incoming = {
+            let network = self.network.clone();
+            let shared = shared.clone();
+            let tx_events = self.tx_events.take();
+            thread::Builder::new().name("IrcAdapter Incoming".to_owned()).spawn(move || {
+                maintain_connection(network, shared, tx_incoming, tx_events, tx_outgoing);
+            }).ok().expect("failed to create incoming thread for IrcAdapter")
+        };
+
+        let flood = self.flood.clone();
+        let outgoing = thread::Builder::new().name("IrcAdapter Outgoing".to_owned()).spawn(move || {
+            deliver(rx_outgoing, shared, flood);
+        }).ok().expect("failed to create outgoing thread for IrcAdapter");
+
+        self.outgoing_thread = Some(outgoing);
+        self.incoming_thread = Some(incoming);
+    }
+
+    fn outgoing(&self) -> Option<Sender<AdapterMsg>> {
+        self.tx_outgoing.clone()
+    }
+
+    /// Send QUIT to the server, whether or not the bot has logged in, and wait for both threads
+    /// to finish. The incoming thread finishes once the server closes the connection.
+    fn shutdown(&mut self) {
+        if let Some(tx) = self.tx_outgoing.take() {
+            let _ = tx.send(AdapterMsg::Shutdown);
+        }
+
+        if let Some(outgoing) = self.outgoing_thread.take() {
+            let _ = outgoing.join();
+        }
+
+        if let Some(incoming) = self.incoming_thread.take() {
+            let _ = incoming.join();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::{BufRead, BufReader, Write};
+    use std::net::{TcpListener, TcpStream};
+    use std::sync::mpsc::{channel, Receiver};
+    use std::thread;
+    use std::time::{Duration, Instant};
+
+    use irc::proto::command::Command;
+    use irc::proto::response::Response;
+    use rustc_serialize::base64::{ToBase64, STANDARD};
+
+    use adapter::ChatAdapter;
+    use event::Event;
+    use message::IncomingMessage;
+    use super::{parse_privmsg, to_event, track_nick, InvitePolicy, IrcAdapter, IrcAuth, IrcConfig};
+
+    /// A stand-in for an IRC server which the test scripts line by line
+    struct Script {
+        reader: BufReader<TcpStream>,
+        writer: TcpStream,
+    }
+
+    impl Script {
+        /// Wait for the adapter to connect
+        fn accept(listener: &TcpListener) -> Script {
+            let (stream, _) = listener.accept().unwrap();
+            stream.set_read_timeout(Some(Duration::from_secs(5))).unwrap();
+
+            Script {
+                reader: BufReader::new(stream.try_clone().unwrap()),
+                writer: stream,
+            }
+        }
+
+        fn send(&mut self, line: &str) {
+            write!(self.writer, "{}\r\n", line).unwrap();
+        }
+
+        /// Read from the adapter until a line starts with `prefix`. Panics if the adapter joins a
+        /// channel first.
+        fn expect(&mut self, prefix: &str) -> String {
+            loop {
+                let mut line = String::new();
+                if self.reader.read_line(&mut line).unwrap() == 0 {
+                    panic!("adapter disconnected while the server waited for {}", prefix);
+                }
+
+                let line = line.trim_right();
+                if line.starts_with(prefix) {
+                    return line.to_owned();
+                }
+                assert!(!line.starts_with("JOIN"), "joined before {}: {}", prefix, line);
+            }
+        }
+
+        /// Shut `irc` down, closing the connection once it sends QUIT like a server would
+        fn shutdown(mut self, mut irc: IrcAdapter) {
+            let closed = thread::spawn(move || {
+                let mut line = String::new();
+                while self.reader.read_line(&mut line).unwrap() > 0 && !line.starts_with("QUIT") {
+                    line.clear();
+                }
+                line
+            });
+
+            irc.shutdown();
+            assert!(closed.join().unwrap().starts_with("QUIT"));
+        }
+    }
+
+    /// An adapter which connects to `listener` and joins #secret
+    fn adapter(listener: &TcpListener) -> IrcAdapter {
+        IrcAdapter::new(IrcConfig {
+            nickname: Some("bot".to_owned()),
+            server: Some("127.0.0.1".to_owned()),
+            port: Some(listener.local_addr().unwrap().port()),
+            channels: Some(vec!["#secret".to_owned()]),
+            .. Default::default()
+        }, "bot")
+    }
+
+    /// Connect an adapter, set up by `setup`, to a scripted server
+    fn connect<F>(setup: F) -> (IrcAdapter, Script, Receiver<IncomingMessage>)
+        where F: FnOnce(IrcAdapter) -> IrcAdapter
+    {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let mut irc = setup(adapter(&listener));
+
+        let (tx, rx) = channel();
+        irc.process_events(tx);
+        let server = Script::accept(&listener);
+        (irc, server, rx)
+    }
+
+    /// A message from joe in `chan`, which is the bot's nick for a query window
+    fn said(chan: &str, text: &str) -> IncomingMessage {
+        let (tx, _) = channel();
+        IncomingMessage::new("IrcAdapter".to_owned(), None, Some(chan.to_owned()),
+                             Some("joe".to_owned()), text.to_owned(), tx)
+    }
+
+    #[test]
+    fn test_addressing() {
+        let irc = IrcAdapter::new(Default::default(), "bot");
+        let address = |chan: &str, text: &str| irc.address(&said(chan, text));
+
+        assert_eq!(address("#chan", "bot: ping"), Some("ping".to_owned()));
+        assert_eq!(address("#chan", "bot, ping"), Some("ping".to_owned()));
+        assert_eq!(address("#chan", "@bot ping"), Some("ping".to_owned()));
+        assert_eq!(address("#chan", "BoT: ping"), Some("ping".to_owned()));
+        assert_eq!(address("#chan", "hey bot ping"), Some("hey ping".to_owned()));
+        assert_eq!(address("#chan", "thanks bot!"), Some("thanks".to_owned()));
+        assert_eq!(address("#chan", "bot2: ping"), None);
+        assert_eq!(address("#chan", "robot ping"), None);
+        assert_eq!(address("#chan", "ping"), None);
+
+        // Everything in a query window is for the bot
+        assert_eq!(address("bot", "ping"), Some("ping".to_owned()));
+        assert_eq!(address("BOT", "ping"), Some("ping".to_owned()));
+    }
+
+    #[test]
+    fn test_addressing_follows_nick() {
+        let irc = IrcAdapter::new(Default::default(), "bot");
+
+        track_nick(&irc.nick, Some("someone"), &Command::NICK("other".to_owned()));
+        assert_eq!(irc.address(&said("#chan", "bot: ping")), Some("ping".to_owned()));
+
+        track_nick(&irc.nick, Some("bot"), &Command::NICK("bot_".to_owned()));
+        assert_eq!(irc.address(&said("#chan", "bot: ping")), None);
+        assert_eq!(irc.address(&said("#chan", "bot_: ping")), Some("ping".to_owned()));
+        assert_eq!(irc.address(&said("bot_", "ping")), Some("ping".to_owned()));
+
+        // The server may give a different nick than the one asked for
+        let welcome = Command::Response(Response::RPL_WELCOME,
+                                        vec!["bot__".to_owned()], None);
+        track_nick(&irc.nick, None, &welcome);
+        assert_eq!(irc.address(&said("#chan", "bot__ ping")), Some("ping".to_owned()));
+    }
+
+    #[test]
+    fn test_parse_privmsg() {
+        assert_eq!(parse_privmsg("hello"), Some(("hello", false)));
+        assert_eq!(parse_privmsg("\x01ACTION waves\x01"), Some(("waves", true)));
+        // Some clients leave off the closing delimiter
+        assert_eq!(parse_privmsg("\x01ACTION waves"), Some(("waves", true)));
+        // Other CTCP requests aren't chat
+        assert_eq!(parse_privmsg("\x01VERSION\x01"), None);
+        assert_eq!(parse_privmsg("\x01PING 1234567890\x01"), None);
+        assert_eq!(parse_privmsg("\x01ACTIONS\x01"), None);
+    }
+
+    #[test]
+    fn test_to_event() {
+        let some = |s: &str| Some(s.to_owned());
+        let cases = vec![
+            (Command::JOIN("#chan".to_owned(), None, None),
+             Some(Event::Join { channel: "#chan".to_owned(), user: "joe".to_owned() })),
+            (Command::PART("#chan".to_owned(), some("bye")),
+             Some(Event::Part { channel: "#chan".to_owned(), user: "joe".to_owned(),
+                                reason: some("bye") })),
+            (Command::QUIT(None),
+             Some(Event::Quit { user: "joe".to_owned(), reason: None })),
+            (Command::NICK("joe_".to_owned()),
+             Some(Event::Nick { old: "joe".to_owned(), new: "joe_".to_owned() })),
+            (Command::TOPIC("#chan".to_owned(), some("rust")),
+             Some(Event::Topic { channel: "#chan".to_owned(), user: some("joe"),
+                                 topic: "rust".to_owned() })),
+            // Asking for the topic doesn't change it
+            (Command::TOPIC("#chan".to_owned(), None), None),
+            (Command::KICK("#chan".to_owned(), "mallory".to_owned(), some("spam")),
+             Some(Event::Kick { channel: "#chan".to_owned(), user: "mallory".to_owned(),
+                                by: some("joe"), reason: some("spam") })),
+            (Command::PRIVMSG("#chan".to_owned(), "hi".to_owned()), None),
+        ];
+
+        for (command, event) in cases {
+            assert_eq!(to_event(&command, Some("joe")), event, "{:?}", command);
+        }
+
+        // Membership changes always come from someone
+        assert_eq!(to_event(&Command::JOIN("#chan".to_owned(), None, None), None), None);
+    }
+
+    #[test]
+    fn test_sasl_before_join() {
+        let (mut irc, mut server, _) = connect(|irc| irc.with_auth(IrcAuth::Sasl {
+            account: "bot".to_owned(),
+            password: "secret".to_owned()
+        }));
+
+        server.expect("CAP REQ :sasl");
+        server.expect("USER");
+        server.send(":irc.test CAP * ACK :sasl");
+        server.expect("AUTHENTICATE PLAIN");
+        server.send("AUTHENTICATE +");
+
+        let credentials = "bot\0bot\0secret".as_bytes().to_base64(STANDARD);
+        assert_eq!(server.expect("AUTHENTICATE"), format!("AUTHENTICATE {}", credentials));
+        server.send(":irc.test 903 bot :SASL authentication successful");
+        server.expect("CAP END");
+
+        server.send(":irc.test 001 bot :Welcome to the test network");
+        server.send(":irc.test 376 bot :End of /MOTD command.");
+        server.expect("JOIN #secret");
+
+        server.shutdown(irc);
+    }
+
+    #[test]
+    fn test_join_after_failed_login() {
+        let (mut irc, mut server, _) = connect(|irc| irc.with_auth(IrcAuth::Sasl {
+            account: "bot".to_owned(),
+            password: "secret".to_owned()
+        }));
+
+        server.expect("CAP REQ :sasl");
+        server.expect("USER");
+        server.send(":irc.test CAP * NAK :sasl");
+        server.expect("CAP END");
+
+        // Channels which don't need the bot to be logged in can still be joined
+        server.send(":irc.test 001 bot :Welcome to the test network");
+        server.send(":irc.test 376 bot :End of /MOTD command.");
+        server.expect("JOIN #secret");
+
+        server.shutdown(irc);
+    }
+
+    #[test]
+    fn test_nickserv_before_join() {
+        let (mut irc, mut server, _) = connect(|irc| {
+            irc.with_auth(IrcAuth::NickServ { password: "secret".to_owned() })
+        });
+
+        server.expect("USER");
+        server.send(":irc.test 001 bot :Welcome to the test network");
+        server.send(":irc.test 376 bot :End of /MOTD command.");
+        server.expect("PRIVMSG NickServ :IDENTIFY secret");
+        server.send(":NickServ!NickServ@services. NOTICE bot :You are now identified for bot.");
+        server.expect("JOIN #secret");
+
+        server.shutdown(irc);
+    }
+
+    #[test]
+    fn test_shutdown_while_logging_in() {
+        let (irc, mut server, _) = connect(|irc| irc.with_auth(IrcAuth::Sasl {
+            account: "bot".to_owned(),
+            password: "secret".to_owned()
+        }));
+
+        // The server never answers, so the bot is stuck waiting on it
+        server.expect("CAP REQ :sasl");
+        server.expect("USER");
+        server.shutdown(irc);
+    }
+
+    #[test]
+    fn test_network_name() {
+        let (mut irc, mut server, rx) = connect(|irc| irc.with_name("testnet"));
+        assert_eq!(irc.get_name(), "testnet");
+
+        server.expect("USER");
+        server.send(":irc.test 001 bot :Welcome to the test network");
+        server.send(":joe!joe@host PRIVMSG #secret :hello");
+
+        let msg = rx.recv_timeout(Duration::from_secs(5)).unwrap();
+        assert_eq!(msg.from_adapter(), "testnet");
+        assert_eq!(msg.server(), Some("testnet"));
+        assert_eq!(msg.get_contents(), "hello");
+
+        server.shutdown(irc);
+    }
+
+    #[test]
+    fn test_invites_and_part() {
+        let (mut irc, mut server, rx) = connect(|irc| {
+            irc.with_invites(InvitePolicy::From(vec!["alice".to_owned()]))
+        });
+
+        server.expect("USER");
+        server.send(":irc.test 001 bot :Welcome to the test network");
+        server.send(":irc.test 376 bot :End of /MOTD command.");
+        server.expect("JOIN #secret");
+
+        server.send(":mallory!m@host INVITE bot #evil");
+        server.send(":Alice!a@host INVITE bot #rust");
+        server.expect("JOIN #rust");
+
+        server.send(":alice!a@host PRIVMSG #rust :leave");
+        let msg = rx.recv_timeout(Duration::from_secs(5)).unwrap();
+        msg.part("#rust").unwrap();
+        server.expect("PART #rust");
+
+        server.shutdown(irc);
+    }
+
+    #[test]
+    fn test_kicked_not_rejoined() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let mut irc = adapter(&listener);
+        let (tx, _rx) = channel();
+        irc.process_events(tx);
+
+        let mut server = Script::accept(&listener);
+        server.expect("USER");
+        server.send(":irc.test 001 bot :Welcome to the test network");
+        server.send(":irc.test 376 bot :End of /MOTD command.");
+        server.expect("JOIN #secret");
+        server.send(":op!op@host KICK #secret bot :bye");
+        drop(server);
+
+        let mut server = Script::accept(&listener);
+        server.expect("USER");
+        server.send(":irc.test 001 bot :Welcome to the test network");
+        server.send(":irc.test 376 bot :End of /MOTD command.");
+        // `expect` fails on a JOIN before the PONG
+        server.send("PING :irc.test");
+        server.expect("PONG");
+
+        server.shutdown(irc);
+    }
+
+    #[test]
+    fn test_replies_held_until_rejoined() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let mut irc = adapter(&listener);
+        let (tx, rx) = channel();
+        irc.process_events(tx);
+
+        let mut server = Script::accept(&listener);
+        server.expect("USER");
+        server.send(":irc.test 001 bot :Welcome to the test network");
+        server.send(":irc.test 376 bot :End of /MOTD command.");
+        server.expect("JOIN #secret");
+        server.send(":joe!joe@host PRIVMSG #secret :ping");
+        let msg = rx.recv_timeout(Duration::from_secs(5)).unwrap();
+
+        // Drop the connection and reply while the adapter is reconnecting
+        drop(server);
+        let mut server = Script::accept(&listener);
+        msg.reply("pong".to_owned()).unwrap();
+
+        server.expect("USER");
+        thread::sleep(Duration::from_millis(1000));
+        server.send(":irc.test 001 bot :Welcome to the test network");
+        server.send(":irc.test 376 bot :End of /MOTD command.");
+        server.expect("JOIN #secret");
+        // `expect` skips lines, so a reply sent before the JOIN would never be found here
+        server.expect("PRIVMSG #secret :pong");
+
+        server.shutdown(irc);
+    }
+
+    #[test]
+    fn test_backoff_until_registered() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let mut irc = adapter(&listener);
+        let (tx, _rx) = channel();
+        irc.process_events(tx);
+
+        // A server which drops the bot before it registers is tried less and less often
+        let mut server = Script::accept(&listener);
+        server.expect("USER");
+        drop(server);
+
+        let mut server = Script::accept(&listener);
+        server.expect("USER");
+        let dropped = Instant::now();
+        drop(server);
+
+        let mut server = Script::accept(&listener);
+        assert!(dropped.elapsed() >= Duration::from_millis(1900));
+        server.expect("USER");
+
+        server.shutdown(irc);
+    }
+}
@@ -4,6 +4,9 @@ use std::sync::mpsc::Sender;
 
 use regex::Regex;
 
+use brain::Namespace;
+use event::IncomingEvent;
+use message::AdapterMsg;
 use message::IncomingMessage;
 
 mod cli;
@@ -13,30 +16,107 @@ pub use self::cli::CliAdapter;
 mod slack;
 #[cfg(feature = "slack-adapter")]
 pub use self::slack::SlackAdapter;
+#[cfg(feature = "slack-adapter")]
+pub use self::slack::SlackEventsAdapter;
+#[cfg(feature = "slack-adapter")]
+pub use self::slack::{EventsConfig, EventsError};
 
 #[cfg(feature = "irc-adapter")]
 mod irc;
 #[cfg(feature = "irc-adapter")]
+pub use self::irc::FloodControl;
+#[cfg(feature = "irc-adapter")]
 pub use self::irc::IrcAdapter;
 #[cfg(feature = "irc-adapter")]
+pub use self::irc::IrcAuth;
+#[cfg(feature = "irc-adapter")]
 pub use self::irc::IrcConfig;
+#[cfg(feature = "irc-adapter")]
+pub use self::irc::InvitePolicy;
 
 /// Chatbot is extensible in both message sources and command handling. To add a
 /// new message source, create a type that implements the `ChatAdapter` trait.
-pub trait ChatAdapter {
+pub trait ChatAdapter: Send {
     /// The name of the adapter which is used internally as a map key and for
     /// debugging.
     fn get_name(&self) -> &str;
 
     /// Users are addressed differently in different chat platforms. This allows the adapter to
-    /// customize detection forn the bot being addressed.
+    /// customize detection forn the bot being addressed. Only messages produced by this adapter are
+    /// checked, and the matched text is removed before addressed handlers see the message.
     fn addresser(&self) -> &Regex;
 
+    /// Decide whether `msg` addresses the bot, returning its contents with the addressing part
+    /// removed if so. The default uses what the adapter recorded when it read the message, if
+    /// anything, and otherwise matches `addresser` against the contents. Adapters which can't
+    /// express addressing as a fixed regex, for example because the bot's name can change while it
+    /// runs, should override this instead.
+    fn address(&self, msg: &IncomingMessage) -> Option<String> {
+        match msg.addressed() {
+            Some(contents) => Some(contents.to_owned()),
+            None => strip_address(self.addresser(), msg.get_contents()),
+        }
+    }
+
     /// ChatAdapters must implement process_events. What this method does will
     /// vary wildly by adapter. At the very least, it must generate IncominMessages from its input,
     /// send them via the `Sender` that's passed in. The main loop has the other end of this
     /// receiver. The IncomingMessage must be constructed with a `Sender<OutgoingMessage>` for
     /// which the adapter listens on the Receiver to send messages back to the service.
     fn process_events(&mut self, Sender<IncomingMessage>);
+
+    /// Called before `process_events` when the bot has event handlers. Adapters which can report
+    /// things like users joining channels should keep `tx_events` and send an
+    /// [`IncomingEvent`](../event/struct.IncomingEvent.html) on it for each one. The default
+    /// drops it, so the adapter produces no events.
+    fn set_event_sender(&mut self, _tx_events: Sender<IncomingEvent>) {}
+
+    /// Called before `process_events` with a namespace of the bot's
+    /// [`Brain`](../brain/trait.Brain.html) for the adapter's own state, such as the channels it
+    /// has joined. The default drops it.
+    fn set_brain(&mut self, _brain: Namespace) {}
+
+    /// The sender for the adapter's outgoing messages, available once `process_events` has been
+    /// called. The chatbot uses it to deliver `AdapterMsg::Proactive` messages. Adapters which
+    /// return `None`, the default, cannot be sent messages proactively.
+    fn outgoing(&self) -> Option<Sender<AdapterMsg>> {
+        None
+    }
+
+    /// Called by the chatbot when it is shutting down. Adapters should deliver an
+    /// `AdapterMsg::Shutdown` to their outgoing thread, disconnect cleanly from their service,
+    /// and wait for their threads to finish before returning. The default implementation does
+    /// nothing.
+    fn shutdown(&mut self) {}
+}
+
+
+/// Check whether `contents` addresses the bot according to `addresser`. Returns the contents with
+/// the addressing part removed if so.
+pub(crate) fn strip_address(addresser: &Regex, contents: &str) -> Option<String> {
+    addresser.find(contents).map(|(start, end)| {
+        let before = contents[..start].trim();
+        let after = contents[end..].trim();
+
+        if before.is_empty() || after.is_empty() {
+            format!("{}{}", before, after)
+        } else {
+            format!("{} {}", before, after)
+        }
+    })
 }
 
+#[cfg(test)]
+mod tests {
+    use adapter::strip_address;
+
+    #[test]
+    fn test_strip_address() {
+        let prefix = regex!(r"^testbot[:,]");
+        assert_eq!(strip_address(&prefix, "testbot: ping"), Some("ping".to_owned()));
+        assert_eq!(strip_address(&prefix, "ping testbot:"), None);
+
+        let mention = regex!(r"<@U0BOT>");
+        assert_eq!(strip_address(&mention, "hey <@U0BOT> ping"), Some("hey ping".to_owned()));
+    }
+}
@@ -0,0 +1,193 @@
+//! Names for Slack's user and channel IDs. Handlers see `@joe` and `#general` where Slack sends
+//! `<@U02ALMR84>` and `<#C024BE91L>`, and `@joe` in a reply becomes a mention again.
+
+use std::collections::HashMap;
+
+use slack;
+use slack::api::rtm::StartResponse;
+
+/// Users and channels by ID, shared by the adapter's threads
+pub struct Directory {
+    users: HashMap<String, String>,
+    channels: HashMap<String, String>,
+}
+
+impl Directory {
+    /// An empty directory
+    pub fn new() -> Directory {
+        Directory {
+            users: HashMap::new(),
+            channels: HashMap::new(),
+        }
+    }
+
+    /// The users, channels and private groups in the RTM start payload
+    pub fn load(start: &StartResponse) -> Directory {
+        let mut directory = Directory::new();
+
+        for user in start.users.iter().flat_map(|users| users.iter()) {
+            directory.add_user(user);
+        }
+        for channel in start.channels.iter().flat_map(|channels| channels.iter()) {
+            directory.add_channel(channel);
+        }
+        for group in start.groups.iter().flat_map(|groups| groups.iter()) {
+            if let (Some(id), Some(name)) = (group.id.as_ref(), group.name.as_ref()) {
+                directory.set_channel(id, name);
+            }
+        }
+
+        directory
+    }
+
+    /// Add or update a user from an event
+    pub fn add_user(&mut self, user: &slack::User) {
+        if let (Some(id), Some(name)) = (user.id.as_ref(), user.name.as_ref()) {
+            self.set_user(id, name);
+        }
+    }
+
+    /// Add or update a channel from an event
+    pub fn add_channel(&mut self, channel: &slack::Channel) {
+        if let (Some(id), Some(name)) = (channel.id.as_ref(), channel.name.as_ref()) {
+            self.set_channel(id, name);
+        }
+    }
+
+    pub fn set_user(&mut self, id: &str, name: &str) {
+        self.users.insert(id.to_owned(), name.to_owned());
+    }
+
+    pub fn set_channel(&mut self, id: &str, name: &str) {
+        self.channels.insert(id.to_owned(), name.to_owned());
+    }
+
+    pub fn user_name(&self, id: &str) -> Option<&str> {
+        self.users.get(id).map(|name| name.as_ref())
+    }
+
+    pub fn channel_name(&self, id: &str) -> Option<&str> {
+        self.channels.get(id).map(|name| name.as_ref())
+    }
+
+    /// The ID of the user called `name`, ignoring case
+    pub fn user_id(&self, name: &str) -> Option<&str> {
+        let name = name.to_lowercase();
+        self.users.iter().find(|&(_, user)| user.to_lowercase() == name).map(|(id, _)| id.as_ref())
+    }
+
+    /// Replace Slack's markup in an incoming message with the text it stands for: mentions
+    /// become `@name` and `#channel`, and links become what the user typed
+    pub fn readable(&self, text: &str) -> String {
+        let mut out = String::with_capacity(text.len());
+        let mut rest = text;
+
+        while let Some(open) = rest.find('<') {
+            let close = match rest[open..].find('>') {
+                Some(close) => open + close,
+                None => break,
+            };
+
+            out.push_str(&unescape(&rest[..open]));
+            out.push_str(&self.readable_markup(&rest[open + 1..close]));
+            rest = &rest[close + 1..];
+        }
+
+        out.push_str(&unescape(rest));
+        out
+    }
+
+    /// The text for one `<...>`, given what's between the brackets
+    fn readable_markup(&self, markup: &str) -> String {
+        let (target, label) = match markup.find('|') {
+            Some(bar) => (&markup[..bar], Some(&markup[bar + 1..])),
+            None => (markup, None),
+        };
+
+        if target.starts_with('@') {
+            let id = &target[1..];
+            format!("@{}", self.user_name(id).or(label).unwrap_or(id))
+        } else if target.starts_with('#') {
+            let id = &target[1..];
+            format!("#{}", self.channel_name(id).or(label).unwrap_or(id))
+        } else if target.starts_with('!') {
+            // <!here>, <!channel> and <!everyone>, or a user group like <!subteam^ID|@team>
+            label.map_or(format!("@{}", &target[1..]), |label| label.to_owned())
+        } else {
+            unescape(label.unwrap_or(target))
+        }
+    }
+
+    /// Turn `@name` for each known user in an outgoing message into a mention
+    pub fn mentions(&self, text: &str) -> String {
+        let mut out = String::with_capacity(text.len());
+        let mut rest = text;
+
+        while let Some(at) = rest.find('@') {
+            out.push_str(&rest[..at]);
+            let after = &rest[at + 1..];
+            let len = after.find(|c| !is_name_char(c)).unwrap_or(after.len());
+            // A sentence may end straight after the name
+            let name = after[..len].trim_right_matches(|c| c == '.' || c == '-');
+
+            // Not in the middle of a word, like an email address
+            let starts_word = out.chars().next_back().map_or(true, |c| !is_name_char(c));
+            match self.user_id(name) {
+                Some(id) if starts_word && !name.is_empty() => out.push_str(&format!("<@{}>", id)),
+                _ => out.push_str(&rest[at..at + 1 + name.len()]),
+            }
+
+            rest = &after[name.len()..];
+        }
+
+        out.push_str(rest);
+        out
+    }
+}
+
+/// Characters Slack allows in user names
+fn is_name_char(c: char) -> bool {
+    c.is_alphanumeric() || c == '.' || c == '_' || c == '-'
+}
+
+/// Undo the escaping Slack applies to `&`, `<` and `>` in message text
+fn unescape(text: &str) -> String {
+    text.replace("&lt;", "<").replace("&gt;", ">").replace("&amp;", "&")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Directory;
+
+    fn directory() -> Directory {
+        let mut directory = Directory::new();
+        directory.set_user("U02ALMR84", "joe");
+        directory.set_user("U0G9QF9C6", "Mary.Jane");
+        directory.set_channel("C024BE91L", "general");
+        directory
+    }
+
+    #[test]
+    fn test_readable() {
+        let directory = directory();
+
+        assert_eq!(directory.readable("<@U02ALMR84>: slap <@U0G9QF9C6> in <#C024BE91L>"),
+                   "@joe: slap @Mary.Jane in #general");
+        assert_eq!(directory.readable("<@U999|ghost> <#C999|random> <!here>"),
+                   "@ghost #random @here");
+        assert_eq!(directory.readable("see <https://example.com|example.com> &lt;3 &amp; bye"),
+                   "see example.com <3 & bye");
+        assert_eq!(directory.readable("<@U999> and a stray <"), "@U999 and a stray <");
+    }
+
+    #[test]
+    fn test_mentions() {
+        let directory = directory();
+
+        assert_eq!(directory.mentions("slaps @JOE and @mary.jane."),
+                   "slaps <@U02ALMR84> and <@U0G9QF9C6>.");
+        assert_eq!(directory.mentions("mail joe@example.com or @nobody"),
+                   "mail joe@example.com or @nobody");
+        assert_eq!(directory.mentions("@"), "@");
+    }
+}
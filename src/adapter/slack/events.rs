@@ -0,0 +1,761 @@
+//! Slack's Events API, the replacement for the legacy RTM socket. Slack sends each event to the
+//! bot as a signed HTTP request, and replies go out through the Web API's `chat.postMessage`.
+
+use std::collections::{HashSet, VecDeque};
+use std::error::Error;
+use std::fmt;
+use std::io::{self, Read};
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr, TcpListener, TcpStream};
+use std::sync::{Arc, Mutex, RwLock};
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::sync::mpsc::{channel, Receiver, Sender};
+use std::thread::{self, JoinHandle};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use regex::Regex;
+use reqwest;
+use rustc_serialize::json::{self, Json};
+
+use adapter::ChatAdapter;
+use message::AdapterMsg;
+use message::IncomingMessage;
+
+use super::{mention_regex, to_event, to_incoming};
+use super::directory::Directory;
+use super::http::{self, Request, RequestError};
+use super::message::{decode_event, Event, Msg};
+use super::signature;
+
+/// How long a client gets to send its whole request
+const REQUEST_TIMEOUT_SECS: u64 = 10;
+
+/// Most connections served at once. Slack retries requests which are turned away.
+const MAX_CONNECTIONS: usize = 32;
+
+/// How many users or channels are asked for in each page of a list
+const LIST_PAGE_SIZE: usize = 200;
+
+/// How many event IDs are remembered to recognize Slack sending an event again
+const MAX_RECENT_EVENTS: usize = 1000;
+
+/// Settings for [`SlackEventsAdapter`](struct.SlackEventsAdapter.html)
+#[derive(Clone, Debug)]
+pub struct EventsConfig {
+    /// Bot token, used for Web API calls
+    pub token: String,
+    /// The app's signing secret. Requests which it didn't sign are refused.
+    pub signing_secret: String,
+    /// Address the HTTP server listens on. The app's Request URL has to reach it.
+    pub listen: String,
+    /// Base URL of the Web API, which method names are appended to
+    pub api_url: String,
+    /// Whether edited messages are handled again
+    pub edits: bool,
+}
+
+impl Default for EventsConfig {
+    /// Listen on port 3000 of every interface and call Slack's own Web API. The token and
+    /// signing secret still have to be filled in.
+    fn default() -> EventsConfig {
+        EventsConfig {
+            token: String::new(),
+            signing_secret: String::new(),
+            listen: "0.0.0.0:3000".to_owned(),
+            api_url: "https://slack.com/api/".to_owned(),
+            edits: false,
+        }
+    }
+}
+
+/// Failures starting the Events API adapter or calling the Web API
+#[derive(Debug)]
+pub enum EventsError {
+    /// The HTTP server couldn't listen, or a response couldn't be read
+    Io(io::Error),
+    /// A Web API request failed
+    Http(reqwest::Error),
+    /// The Web API refused a call. Holds the method and Slack's error code.
+    Api(String),
+}
+
+impl fmt::Display for EventsError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            EventsError::Io(ref err) => write!(f, "I/O error: {}", err),
+            EventsError::Http(ref err) => write!(f, "HTTP error: {}", err),
+            EventsError::Api(ref err) => write!(f, "Slack API error: {}", err),
+        }
+    }
+}
+
+impl Error for EventsError {
+    fn description(&self) -> &str {
+        match *self {
+            EventsError::Io(ref err) => err.description(),
+            EventsError::Http(ref err) => err.description(),
+            EventsError::Api(_) => "Slack API call failed",
+        }
+    }
+
+    fn cause(&self) -> Option<&Error> {
+        match *self {
+            EventsError::Io(ref err) => Some(err),
+            EventsError::Http(ref err) => Some(err),
+            EventsError::Api(_) => None,
+        }
+    }
+}
+
+impl From<io::Error> for EventsError {
+    fn from(err: io::Error) -> EventsError {
+        EventsError::Io(err)
+    }
+}
+
+impl From<reqwest::Error> for EventsError {
+    fn from(err: reqwest::Error) -> EventsError {
+        EventsError::Http(err)
+    }
+}
+
+/// Calls to Slack's Web API
+struct WebApi {
+    client: reqwest::Client,
+    token: String,
+    url: String,
+}
+
+impl WebApi {
+    fn new(token: &str, url: &str) -> Result<WebApi, EventsError> {
+        Ok(WebApi {
+            client: try!(reqwest::Client::new()),
+            token: token.to_owned(),
+            url: url.to_owned(),
+        })
+    }
+
+    /// Call `method` with `params`, returning Slack's response if it says the call worked
+    fn call(&self, method: &str,
+            params: &[(&'static str, String)]) -> Result<json::Object, EventsError> {
+        let mut form = vec![("token", self.token.clone())];
+        form.extend(params.iter().cloned());
+
+        let url = format!("{}{}", self.url, method);
+        let mut response = try!(self.client.post(&url[..]).form(&form).send());
+        let mut body = String::new();
+        try!(response.read_to_string(&mut body));
+
+        let obj = match Json::from_str(&body) {
+            Ok(Json::Object(obj)) => obj,
+            _ => {
+                return Err(EventsError::Api(format!("{}: unexpected response {}", method, body)))
+            }
+        };
+
+        if obj.get("ok").and_then(|ok| ok.as_boolean()) == Some(true) {
+            Ok(obj)
+        } else {
+            let error = obj.get("error").and_then(|error| error.as_string());
+            Err(EventsError::Api(format!("{}: {}", method, error.unwrap_or("unknown_error"))))
+        }
+    }
+
+    /// Every item in the `key` array of `method`'s responses, following Slack's cursor through
+    /// the pages
+    fn list(&self, method: &str, key: &str,
+            params: &[(&'static str, String)]) -> Result<Vec<Json>, EventsError> {
+        let mut items = Vec::new();
+        let mut cursor = String::new();
+
+        loop {
+            let mut page = params.to_vec();
+            page.push(("limit", LIST_PAGE_SIZE.to_string()));
+            if !cursor.is_empty() {
+                page.push(("cursor", cursor));
+            }
+
+            let mut response = try!(self.call(method, &page));
+            if let Some(Json::Array(page_items)) = response.remove(key) {
+                items.extend(page_items);
+            }
+
+            let next = response.get("response_metadata").and_then(|meta| meta.find("next_cursor"))
+                               .and_then(|next| next.as_string());
+            cursor = match next {
+                Some(next) if !next.is_empty() => next.to_owned(),
+                _ => return Ok(items),
+            };
+        }
+    }
+
+    /// The names of the workspace's users and of the channels the bot can see
+    fn directory(&self) -> Result<Directory, EventsError> {
+        let mut directory = Directory::new();
+
+        for user in try!(self.list("users.list", "members", &[])) {
+            if let Some((id, name)) = user.as_object().and_then(named) {
+                directory.set_user(id, name);
+            }
+        }
+
+        let types = ("types", "public_channel,private_channel".to_owned());
+        for channel in try!(self.list("conversations.list", "channels", &[types])) {
+            if let Some((id, name)) = channel.as_object().and_then(named) {
+                directory.set_channel(id, name);
+            }
+        }
+
+        Ok(directory)
+    }
+
+    /// The bot's user ID, which mentions of it contain
+    fn own_id(&self) -> Result<String, EventsError> {
+        let response = try!(self.call("auth.test", &[]));
+        match response.get("user_id").and_then(|id| id.as_string()) {
+            Some(id) => Ok(id.to_owned()),
+            None => Err(EventsError::Api("auth.test: no user_id".to_owned())),
+        }
+    }
+}
+
+/// SlackEventsAdapter receives events from Slack's [Events API](https://api.slack.com/events-api)
+/// with a small HTTP server, and posts replies with `chat.postMessage`. Unlike
+/// [`SlackAdapter`](struct.SlackAdapter.html) it needs no long lived connection, but Slack has to
+/// be able to reach the server at the app's Request URL.
+///
+/// Requests must be signed with the app's signing secret. The `url_verification` challenge Slack
+/// sends when the Request URL is set is answered, and `message` events are handled like the RTM
+/// adapter's: mentions become readable names, the bot is addressed by mentioning it anywhere or in
+/// a direct message, and other bots' messages and the bot's own are ignored.
+///
+/// Each event is handled once, even when Slack sends it again because it thinks the first
+/// delivery wasn't received. Events are recognized by their `event_id`.
+///
+/// Subscribe the app to `message.channels`, `message.groups` and `message.im` for the
+/// conversations it should hear. Names are listed with `users.list` and `conversations.list` at
+/// the start, which need the `users:read`, `channels:read` and `groups:read` scopes, and kept up
+/// to date with `user_change`, `team_join`, `channel_created` and `channel_rename` events.
+pub struct SlackEventsAdapter {
+    config: EventsConfig,
+    listener: Option<TcpListener>,
+    addr: SocketAddr,
+    api: Option<WebApi>,
+    directory: Arc<RwLock<Directory>>,
+    id: String,
+    addresser_regex: Regex,
+    stopping: Arc<AtomicBool>,
+    tx_outgoing: Option<Sender<AdapterMsg>>,
+    receiver_thread: Option<JoinHandle<()>>,
+    sender_thread: Option<JoinHandle<()>>,
+}
+
+impl SlackEventsAdapter {
+    /// Start listening on `config.listen`, learn the bot's user ID with `auth.test`, and list the
+    /// users and channels
+    pub fn start(config: EventsConfig) -> Result<SlackEventsAdapter, EventsError> {
+        let listener = try!(TcpListener::bind(&config.listen[..]));
+        let addr = try!(listener.local_addr());
+        let api = try!(WebApi::new(&config.token, &config.api_url));
+        let id = try!(api.own_id());
+        let directory = try!(api.directory());
+
+        Ok(SlackEventsAdapter {
+            config: config,
+            listener: Some(listener),
+            addr: addr,
+            api: Some(api),
+            directory: Arc::new(RwLock::new(directory)),
+            addresser_regex: mention_regex(&id),
+            id: id,
+            stopping: Arc::new(AtomicBool::new(false)),
+            tx_outgoing: None,
+            receiver_thread: None,
+            sender_thread: None,
+        })
+    }
+
+    /// The address the HTTP server is listening on, such as the port picked when `listen` asked
+    /// for port 0
+    pub fn local_addr(&self) -> SocketAddr {
+        self.addr
+    }
+}
+
+/// The IDs of the events handled most recently
+struct RecentEvents {
+    ids: HashSet<String>,
+    order: VecDeque<String>,
+}
+
+impl RecentEvents {
+    fn new() -> RecentEvents {
+        RecentEvents {
+            ids: HashSet::new(),
+            order: VecDeque::new(),
+        }
+    }
+
+    /// Remember `id`, returning whether it is new. The oldest ID is forgotten once
+    /// `MAX_RECENT_EVENTS` are remembered.
+    fn first(&mut self, id: &str) -> bool {
+        if !self.ids.insert(id.to_owned()) {
+            return false;
+        }
+
+        self.order.push_back(id.to_owned());
+        if self.order.len() > MAX_RECENT_EVENTS {
+            if let Some(oldest) = self.order.pop_front() {
+                self.ids.remove(&oldest);
+            }
+        }
+        true
+    }
+}
+
+/// A place among the `MAX_CONNECTIONS` served at once, given up when dropped
+struct Slot(Arc<AtomicUsize>);
+
+impl Slot {
+    /// Take a place, unless they are all in use
+    fn take(active: &Arc<AtomicUsize>) -> Option<Slot> {
+        if active.fetch_add(1, Ordering::SeqCst) < MAX_CONNECTIONS {
+            Some(Slot(active.clone()))
+        } else {
+            active.fetch_sub(1, Ordering::SeqCst);
+            None
+        }
+    }
+}
+
+impl Drop for Slot {
+    fn drop(&mut self) {
+        self.0.fetch_sub(1, Ordering::SeqCst);
+    }
+}
+
+/// Everything needed to turn requests into messages. Each connection is served by its own copy.
+#[derive(Clone)]
+struct EventReceiver {
+    signing_secret: String,
+    self_id: String,
+    edits: bool,
+    mention: Regex,
+    directory: Arc<RwLock<Directory>>,
+    recent: Arc<Mutex<RecentEvents>>,
+    tx_incoming: Sender<IncomingMessage>,
+    tx_outgoing: Sender<AdapterMsg>,
+}
+
+impl EventReceiver {
+    /// Read one request from `stream` and answer it
+    fn serve(&self, stream: TcpStream) {
+        let _ = stream.set_read_timeout(Some(Duration::from_secs(REQUEST_TIMEOUT_SECS)));
+
+        let (status, body) = match Request::read(&stream) {
+            Ok(request) => self.handle(&request),
+            Err(RequestError::TooLarge) => ("413 Payload Too Large", String::new()),
+            Err(e) => {
+                println!("SlackEventsAdapter: bad request: {}", e);
+                ("400 Bad Request", String::new())
+            }
+        };
+
+        if let Err(e) = http::respond(&stream, status, &body) {
+            println!("SlackEventsAdapter: failed to respond: {}", e);
+        }
+    }
+
+    /// The status and body to answer `request` with
+    fn handle(&self, request: &Request) -> (&'static str, String) {
+        if request.method != "POST" {
+            return ("405 Method Not Allowed", String::new());
+        }
+
+        if !self.signed(request) {
+            println!("SlackEventsAdapter: refused a request without a valid signature");
+            return ("401 Unauthorized", String::new());
+        }
+
+        let payload = match String::from_utf8(request.body.clone()).ok()
+                                .and_then(|body| Json::from_str(&body).ok()) {
+            Some(Json::Object(payload)) => payload,
+            _ => return ("400 Bad Request", String::new()),
+        };
+
+        match payload.get("type").and_then(|kind| kind.as_string()) {
+            // Sent when the Request URL is set, to check the bot is listening there
+            Some("url_verification") => {
+                let challenge = payload.get("challenge").and_then(|value| value.as_string());
+                ("200 OK", challenge.unwrap_or_default().to_owned())
+            },
+            Some("event_callback") => {
+                // Retries carry the same ID as the first delivery
+                let first = match payload.get("event_id").and_then(|id| id.as_string()) {
+                    Some(id) => self.recent.lock().unwrap().first(id),
+                    None => true,
+                };
+
+                if let (true, Some(&Json::Object(ref event))) = (first, payload.get("event")) {
+                    self.dispatch(event.clone());
+                }
+                ("200 OK", String::new())
+            },
+            _ => ("200 OK", String::new()),
+        }
+    }
+
+    /// Whether Slack signed `request` with the signing secret, recently
+    fn signed(&self, request: &Request) -> bool {
+        let now = SystemTime::now().duration_since(UNIX_EPOCH).map(|now| now.as_secs());
+
+        match (request.header("X-Slack-Request-Timestamp"), request.header("X-Slack-Signature")) {
+            (Some(timestamp), Some(sig)) => {
+                signature::verify(&self.signing_secret, timestamp, &request.body, sig,
+                                  now.unwrap_or(0))
+            },
+            _ => false,
+        }
+    }
+
+    /// Handle the `event` from an `event_callback`
+    fn dispatch(&self, event: json::Object) {
+        let kind = event.get("type").and_then(|kind| kind.as_string()).unwrap_or("").to_owned();
+
+        match kind.as_ref() {
+            "message" => self.message(event),
+            "user_change" | "team_join" => {
+                if let Some((id, name)) = id_and_name(&event, "user") {
+                    self.directory.write().unwrap().set_user(id, name);
+                }
+            },
+            "channel_created" | "channel_rename" | "group_rename" => {
+                if let Some((id, name)) = id_and_name(&event, "channel") {
+                    self.directory.write().unwrap().set_channel(id, name);
+                }
+            },
+            _ => (),
+        }
+    }
+
+    /// Pass a message on to handlers, unless it is from a bot or an edit that shouldn't be
+    fn message(&self, event: json::Object) {
+        let (data, action) = match decode_event(event) {
+            Ok(Event::Message(Msg::Plain(data))) => (data, false),
+            Ok(Event::Message(Msg::Me(data))) => (data, true),
+            Ok(Event::Message(Msg::Changed(_))) if !self.edits => return,
+            Ok(Event::Message(Msg::Changed(data))) => (data, false),
+            // Bots, deletions, joins and the like aren't for handlers
+            Ok(_) => return,
+            Err(e) => {
+                println!("SlackEventsAdapter: failed to decode message: {}", e);
+                return
+            }
+        };
+
+        // The bot's own replies are sent back to it as events
+        if data.user() == self.self_id {
+            return
+        }
+
+        let mut incoming = to_incoming("SlackEventsAdapter", &self.directory.read().unwrap(),
+                                       &self.mention, Some(data.channel().to_owned()),
+                                       Some(data.user().to_owned()), data.text().to_owned(),
+                                       self.tx_outgoing.clone());
+        incoming.set_id(Some(data.ts().to_owned()));
+        incoming.set_thread(data.thread_ts().map(|ts| ts.to_owned()));
+        incoming.set_action(action);
+
+        if let Err(e) = self.tx_incoming.send(incoming) {
+            println!("SlackEventsAdapter: chatbot not receiving messages: {}", e);
+        }
+    }
+}
+
+/// The `id` and `name` of the object at `key` in `event`, such as the user in `user_change`
+fn id_and_name<'a>(event: &'a json::Object, key: &str) -> Option<(&'a str, &'a str)> {
+    event.get(key).and_then(|obj| obj.as_object()).and_then(named)
+}
+
+/// The `id` and `name` of a user or channel
+fn named(obj: &json::Object) -> Option<(&str, &str)> {
+    match (obj.get("id").and_then(|id| id.as_string()),
+           obj.get("name").and_then(|name| name.as_string())) {
+        (Some(id), Some(name)) => Some((id, name)),
+        _ => None,
+    }
+}
+
+/// Post messages from the bot until the adapter shuts down
+fn deliver(rx_outgoing: Receiver<AdapterMsg>, api: WebApi, directory: Arc<RwLock<Directory>>) {
+    for msg in rx_outgoing.iter() {
+        if let AdapterMsg::Shutdown = msg {
+            break
+        }
+
+        // Posting to a user ID sends the message to the bot's direct message channel with them
+        if let Some(mut out) = to_event("SlackEventsAdapter", msg, |user| Ok(user.to_owned())) {
+            out.link_mentions(&directory.read().unwrap());
+
+            let method = if out.is_action() { "chat.meMessage" } else { "chat.postMessage" };
+            if let Err(e) = api.call(method, &out.post_params()) {
+                println!("SlackEventsAdapter: failed to post message: {}", e);
+            }
+        }
+    }
+}
+
+impl ChatAdapter for SlackEventsAdapter {
+    /// SlackEventsAdapter name
+    fn get_name(&self) -> &str {
+        "SlackEventsAdapter"
+    }
+
+    /// Matches a mention of the bot in a message as Slack sends it. The receiver thread uses it
+    /// before mentions are made readable and records the result on the message.
+    fn addresser(&self) -> &Regex {
+        &self.addresser_regex
+    }
+
+    fn process_events(&mut self, tx_incoming: Sender<IncomingMessage>) {
+        println!("SlackEventsAdapter: listening on {}", self.addr);
+        let (tx_outgoing, rx_outgoing) = channel();
+        self.tx_outgoing = Some(tx_outgoing.clone());
+
+        let listener = self.listener.take().unwrap();
+        let api = self.api.take().unwrap();
+        let directory = self.directory.clone();
+
+        let receiver = EventReceiver {
+            signing_secret: self.config.signing_secret.clone(),
+            self_id: self.id.clone(),
+            edits: self.config.edits,
+            mention: self.addresser_regex.clone(),
+            directory: directory.clone(),
+            recent: Arc::new(Mutex::new(RecentEvents::new())),
+            tx_incoming: tx_incoming,
+            tx_outgoing: tx_outgoing,
+        };
+        let stopping = self.stopping.clone();
+        let receiver_thread = thread::Builder::new()
+            .name("Chatbot Slack Events Receiver".to_owned())
+            .spawn(move || {
+                let active = Arc::new(AtomicUsize::new(0));
+
+                for stream in listener.incoming() {
+                    if stopping.load(Ordering::SeqCst) {
+                        break
+                    }
+
+                    // Slack gives up on a request after three seconds, so a client which is slow to
+                    // send one mustn't hold up the rest
+                    match stream {
+                        Ok(stream) => match Slot::take(&active) {
+                            Some(slot) => {
+                                let receiver = receiver.clone();
+                                thread::spawn(move || {
+                                    receiver.serve(stream);
+                                    drop(slot);
+                                });
+                            },
+                            None => {
+                                println!("SlackEventsAdapter: too many connections; refused one");
+                                let _ = http::respond(&stream, "503 Service Unavailable", "");
+                            },
+                        },
+                        Err(e) => println!("SlackEventsAdapter: failed to accept: {}", e),
+                    }
+                }
+            }).ok().expect("failed to create thread for slack events receiver");
+
+        let sender_thread = thread::Builder::new()
+            .name("Chatbot Slack Events Sender".to_owned())
+            .spawn(move || {
+                deliver(rx_outgoing, api, directory);
+            }).ok().expect("failed to create thread for slack events sender");
+
+        self.receiver_thread = Some(receiver_thread);
+        self.sender_thread = Some(sender_thread);
+    }
+
+    fn outgoing(&self) -> Option<Sender<AdapterMsg>> {
+        self.tx_outgoing.clone()
+    }
+
+    /// Stop listening, post the replies already sent to the adapter, and wait for both threads
+    fn shutdown(&mut self) {
+        self.stopping.store(true, Ordering::SeqCst);
+
+        // The receiver thread only checks for shutdown between requests, so wake it with one
+        let mut wake = self.addr;
+        if wake.ip().is_unspecified() {
+            wake.set_ip(match wake.ip() {
+                IpAddr::V4(_) => IpAddr::V4(Ipv4Addr::new(127, 0, 0, 1)),
+                IpAddr::V6(_) => IpAddr::V6(Ipv6Addr::new(0, 0, 0, 0, 0, 0, 0, 1)),
+            });
+        }
+        if let Some(receiver) = self.receiver_thread.take() {
+            if TcpStream::connect(wake).is_ok() {
+                let _ = receiver.join();
+            }
+        }
+
+        if let Some(tx) = self.tx_outgoing.take() {
+            let _ = tx.send(AdapterMsg::Shutdown);
+        }
+
+        if let Some(sender) = self.sender_thread.take() {
+            let _ = sender.join();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::{Read, Write};
+    use std::net::{SocketAddr, TcpListener, TcpStream};
+    use std::sync::Arc;
+    use std::sync::atomic::AtomicUsize;
+    use std::sync::mpsc::channel;
+    use std::thread;
+    use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+    use adapter::ChatAdapter;
+    use adapter::slack::http::{self, Request};
+    use adapter::slack::signature;
+    use super::{EventsConfig, RecentEvents, SlackEventsAdapter, Slot};
+    use super::{MAX_CONNECTIONS, MAX_RECENT_EVENTS};
+
+    /// POST `body` to the adapter, signed with `secret`, and return the response
+    fn post(addr: SocketAddr, secret: &str, body: &str) -> String {
+        post_with(addr, secret, "", body)
+    }
+
+    /// `post`, with `headers` added to the request
+    fn post_with(addr: SocketAddr, secret: &str, headers: &str, body: &str) -> String {
+        let timestamp = SystemTime::now().duration_since(UNIX_EPOCH).unwrap().as_secs()
+                                                                      .to_string();
+        let sig = signature::sign(secret, &timestamp, body.as_bytes());
+
+        // Slack waits three seconds for an answer
+        let mut stream = TcpStream::connect(addr).unwrap();
+        stream.set_read_timeout(Some(Duration::from_secs(3))).unwrap();
+        write!(stream, "POST /slack/events HTTP/1.1\r\nX-Slack-Request-Timestamp: {}\r\n\
+                        X-Slack-Signature: {}\r\n{}Content-Length: {}\r\n\r\n{}",
+               timestamp, sig, headers, body.len(), body).unwrap();
+
+        let mut response = String::new();
+        stream.read_to_string(&mut response).unwrap();
+        response
+    }
+
+    #[test]
+    fn test_events_round_trip() {
+        // A stand in for the Web API, which reports the messages posted to it
+        let api = TcpListener::bind("127.0.0.1:0").unwrap();
+        let api_addr = api.local_addr().unwrap();
+        let (tx_posted, rx_posted) = channel();
+        thread::spawn(move || {
+            for stream in api.incoming() {
+                let stream = stream.unwrap();
+                let request = Request::read(&stream).unwrap();
+                let body = String::from_utf8(request.body).unwrap();
+
+                if request.path.ends_with("auth.test") {
+                    http::respond(&stream, "200 OK", r#"{"ok":true,"user_id":"UBOT"}"#).unwrap();
+                } else if request.path.ends_with("users.list") {
+                    http::respond(&stream, "200 OK", r#"{"ok":true,"members":[
+                        {"id":"UBOT","name":"bot"},{"id":"U02ALMR84","name":"joe"}]}"#).unwrap();
+                } else if request.path.ends_with("conversations.list") {
+                    // Two pages, to check the cursor is followed
+                    let page = if body.contains("cursor=dGVhbTpDMDI0") {
+                        r#"{"ok":true,"channels":[{"id":"C024BE91L","name":"general"}],
+                            "response_metadata":{"next_cursor":""}}"#
+                    } else {
+                        r#"{"ok":true,"channels":[{"id":"C0G9QF9GW","name":"random"}],
+                            "response_metadata":{"next_cursor":"dGVhbTpDMDI0"}}"#
+                    };
+                    http::respond(&stream, "200 OK", page).unwrap();
+                } else {
+                    http::respond(&stream, "200 OK", r#"{"ok":true}"#).unwrap();
+                    tx_posted.send((request.path, body)).unwrap();
+                }
+            }
+        });
+
+        let mut adapter = SlackEventsAdapter::start(EventsConfig {
+            token: "xoxb-test".to_owned(),
+            signing_secret: "secret".to_owned(),
+            listen: "127.0.0.1:0".to_owned(),
+            api_url: format!("http://{}/api/", api_addr),
+            .. EventsConfig::default()
+        }).unwrap();
+        let addr = adapter.local_addr();
+        let (tx_incoming, rx_incoming) = channel();
+        adapter.process_events(tx_incoming);
+
+        // A client which connects and says nothing doesn't hold up Slack's requests
+        let _idle = TcpStream::connect(addr).unwrap();
+
+        let challenge = r#"{"type":"url_verification","challenge":"3eZbrw1aBm2r"}"#;
+        assert!(post(addr, "wrong", challenge).starts_with("HTTP/1.1 401"));
+        let response = post(addr, "secret", challenge);
+        assert!(response.starts_with("HTTP/1.1 200"));
+        assert!(response.ends_with("\r\n\r\n3eZbrw1aBm2r"));
+
+        let event = r#"{"type":"event_callback","event_id":"Ev08MFMKH6",
+            "event":{"type":"message","channel":"C024BE91L","user":"U02ALMR84",
+            "text":"<@UBOT> ping","ts":"1432563914.000007"}}"#;
+        assert!(post(addr, "secret", event).starts_with("HTTP/1.1 200"));
+
+        let incoming = rx_incoming.recv_timeout(Duration::from_secs(5)).unwrap();
+        assert_eq!(incoming.addressed(), Some("ping"));
+        assert_eq!(incoming.id(), Some("1432563914.000007"));
+        assert_eq!(incoming.user_name(), Some("joe"));
+        assert_eq!(incoming.channel_name(), Some("general"));
+        incoming.reply("pong".to_owned()).unwrap();
+
+        // A retry of an event already handled is acknowledged but not handled again
+        let retry = "X-Slack-Retry-Num: 1\r\nX-Slack-Retry-Reason: http_timeout\r\n";
+        assert!(post_with(addr, "secret", retry, event).starts_with("HTTP/1.1 200"));
+        assert!(rx_incoming.recv_timeout(Duration::from_millis(500)).is_err());
+
+        // A retry of one which never arrived is
+        let missed = event.replace("Ev08MFMKH6", "Ev08MFMKH7");
+        assert!(post_with(addr, "secret", retry, &missed).starts_with("HTTP/1.1 200"));
+        assert!(rx_incoming.recv_timeout(Duration::from_secs(5)).is_ok());
+
+        let (path, body) = rx_posted.recv_timeout(Duration::from_secs(5)).unwrap();
+        assert_eq!(path, "/api/chat.postMessage");
+        assert!(body.contains("channel=C024BE91L"));
+        assert!(body.contains("text=pong"));
+
+        adapter.shutdown();
+    }
+
+    #[test]
+    fn test_recent_events() {
+        let mut recent = RecentEvents::new();
+        assert!(recent.first("Ev0"));
+        assert!(!recent.first("Ev0"));
+
+        for i in 1..MAX_RECENT_EVENTS + 1 {
+            assert!(recent.first(&format!("Ev{}", i)));
+        }
+
+        // Only the oldest has been forgotten
+        assert!(recent.first("Ev0"));
+        assert!(!recent.first(&format!("Ev{}", MAX_RECENT_EVENTS)));
+    }
+
+    #[test]
+    fn test_connection_slots() {
+        let active = Arc::new(AtomicUsize::new(0));
+        let mut slots = (0..MAX_CONNECTIONS).map(|_| Slot::take(&active).unwrap())
+                                            .collect::<Vec<_>>();
+        assert!(Slot::take(&active).is_none());
+
+        // Finishing with a connection makes room for another
+        slots.pop();
+        assert!(Slot::take(&active).is_some());
+    }
+}
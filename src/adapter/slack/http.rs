@@ -0,0 +1,195 @@
+//! Just enough HTTP/1.1 to receive Events API requests: one request per connection, with a
+//! `Content-Length` body.
+
+use std::error::Error;
+use std::fmt;
+use std::io::{self, BufRead, BufReader, Read, Write};
+use std::net::TcpStream;
+
+/// Largest request accepted, headers included. Event payloads are a few kilobytes.
+const MAX_REQUEST_BYTES: u64 = 1024 * 1024;
+
+/// An HTTP request
+pub struct Request {
+    pub method: String,
+    pub path: String,
+    headers: Vec<(String, String)>,
+    pub body: Vec<u8>,
+}
+
+/// Why a request couldn't be read
+#[derive(Debug)]
+pub enum RequestError {
+    /// The connection failed, or what was sent isn't HTTP
+    Io(io::Error),
+    /// The body is longer than `MAX_REQUEST_BYTES`
+    TooLarge,
+}
+
+impl fmt::Display for RequestError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            RequestError::Io(ref err) => write!(f, "{}", err),
+            RequestError::TooLarge => write!(f, "body longer than {} bytes", MAX_REQUEST_BYTES),
+        }
+    }
+}
+
+impl Error for RequestError {
+    fn description(&self) -> &str {
+        match *self {
+            RequestError::Io(ref err) => err.description(),
+            RequestError::TooLarge => "request too large",
+        }
+    }
+
+    fn cause(&self) -> Option<&Error> {
+        match *self {
+            RequestError::Io(ref err) => Some(err),
+            RequestError::TooLarge => None,
+        }
+    }
+}
+
+impl From<io::Error> for RequestError {
+    fn from(err: io::Error) -> RequestError {
+        RequestError::Io(err)
+    }
+}
+
+fn malformed(what: &str) -> RequestError {
+    RequestError::Io(io::Error::new(io::ErrorKind::InvalidData,
+                                    format!("malformed request: {}", what)))
+}
+
+impl Request {
+    /// Read one request from `stream`. Bodies longer than `MAX_REQUEST_BYTES` are refused before
+    /// any of them is read.
+    pub fn read(stream: &TcpStream) -> Result<Request, RequestError> {
+        let mut reader = BufReader::new(stream.take(MAX_REQUEST_BYTES));
+
+        let mut line = String::new();
+        try!(reader.read_line(&mut line));
+        let mut parts = line.split_whitespace();
+        let (method, path) = match (parts.next(), parts.next()) {
+            (Some(method), Some(path)) => (method.to_owned(), path.to_owned()),
+            _ => return Err(malformed("request line")),
+        };
+
+        let mut headers = Vec::new();
+        loop {
+            let mut line = String::new();
+            if try!(reader.read_line(&mut line)) == 0 {
+                return Err(malformed("headers never ended"));
+            }
+
+            let line = line.trim_right();
+            if line.is_empty() {
+                break;
+            }
+
+            match line.find(':') {
+                Some(colon) => {
+                    headers.push((line[..colon].trim().to_owned(),
+                                  line[colon + 1..].trim().to_owned()));
+                },
+                None => return Err(malformed("header")),
+            }
+        }
+
+        let mut request = Request {
+            method: method,
+            path: path,
+            headers: headers,
+            body: Vec::new(),
+        };
+
+        let length = match request.header("Content-Length") {
+            Some(length) => try!(length.parse::<u64>().map_err(|_| malformed("length"))),
+            None => 0,
+        };
+        if length > MAX_REQUEST_BYTES {
+            return Err(RequestError::TooLarge);
+        }
+
+        try!(reader.take(length).read_to_end(&mut request.body));
+        if (request.body.len() as u64) < length {
+            return Err(malformed("body ended early"));
+        }
+
+        Ok(request)
+    }
+
+    /// The value of the header called `name`, ignoring case
+    pub fn header(&self, name: &str) -> Option<&str> {
+        self.headers.iter()
+            .find(|&&(ref key, _)| key.eq_ignore_ascii_case(name))
+            .map(|&(_, ref value)| value.as_ref())
+    }
+}
+
+/// Write a plain text response and close the connection. `status` is the code and reason, e.g.
+/// `200 OK`.
+pub fn respond(mut stream: &TcpStream, status: &str, body: &str) -> io::Result<()> {
+    write!(stream, "HTTP/1.1 {}\r\nContent-Type: text/plain\r\nContent-Length: {}\r\n\
+                    Connection: close\r\n\r\n{}", status, body.len(), body)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::{Read, Write};
+    use std::net::{TcpListener, TcpStream};
+    use std::thread;
+
+    use super::{respond, Request, RequestError};
+
+    #[test]
+    fn test_request_and_response() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+
+        let client = thread::spawn(move || {
+            let mut stream = TcpStream::connect(addr).unwrap();
+            stream.write_all(b"POST /slack/events HTTP/1.1\r\nHost: bot\r\n\
+                               content-length: 11\r\n\r\n{\"a\": true}").unwrap();
+
+            let mut response = String::new();
+            stream.read_to_string(&mut response).unwrap();
+            response
+        });
+
+        let (stream, _) = listener.accept().unwrap();
+        let request = Request::read(&stream).unwrap();
+        assert_eq!(request.method, "POST");
+        assert_eq!(request.path, "/slack/events");
+        assert_eq!(request.header("Content-Length"), Some("11"));
+        assert_eq!(request.body, b"{\"a\": true}");
+
+        respond(&stream, "200 OK", "hello").unwrap();
+        drop(stream);
+
+        let response = client.join().unwrap();
+        assert!(response.starts_with("HTTP/1.1 200 OK\r\n"));
+        assert!(response.ends_with("\r\n\r\nhello"));
+    }
+
+    #[test]
+    fn test_too_large() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+
+        let client = thread::spawn(move || {
+            let mut stream = TcpStream::connect(addr).unwrap();
+            stream.write_all(b"POST /slack/events HTTP/1.1\r\n\
+                               Content-Length: 99999999999\r\n\r\n{}").unwrap();
+            stream
+        });
+
+        let (stream, _) = listener.accept().unwrap();
+        match Request::read(&stream) {
+            Err(RequestError::TooLarge) => (),
+            _ => panic!("expected RequestError::TooLarge"),
+        }
+        client.join().unwrap();
+    }
+}
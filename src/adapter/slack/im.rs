@@ -0,0 +1,71 @@
+//! Direct message (IM) channels, which private replies are sent through. Slack only delivers
+//! messages to channel IDs, so a user's IM channel has to be looked up or opened first.
+
+use std::collections::HashMap;
+
+use reqwest;
+use slack::api::im;
+
+/// The IM channel for each user the bot has a conversation with, by user ID
+pub struct DirectMessages {
+    token: String,
+    client: Option<reqwest::Client>,
+    channels: HashMap<String, String>,
+}
+
+impl DirectMessages {
+    /// No known IM channels yet. New ones are opened with `token`.
+    pub fn new(token: &str) -> DirectMessages {
+        DirectMessages {
+            token: token.to_owned(),
+            client: None,
+            channels: HashMap::new(),
+        }
+    }
+
+    /// Record that `channel` is the IM channel with `user`
+    pub fn remember(&mut self, user: &str, channel: &str) {
+        self.channels.insert(user.to_owned(), channel.to_owned());
+    }
+
+    /// The IM channel with `user`, opening one with the Web API if there isn't one yet
+    pub fn channel(&mut self, user: &str) -> Result<String, String> {
+        if let Some(channel) = self.channels.get(user) {
+            return Ok(channel.to_owned());
+        }
+
+        if self.client.is_none() {
+            self.client = Some(try!(reqwest::Client::new().map_err(|e| format!("{:?}", e))));
+        }
+
+        let request = im::OpenRequest {
+            user: user,
+            .. Default::default()
+        };
+        let client = self.client.as_ref().unwrap();
+        let response = try!(im::open(client, &self.token, &request)
+                                .map_err(|e| format!("{:?}", e)));
+
+        match response.channel.and_then(|channel| channel.id) {
+            Some(channel) => {
+                self.remember(user, &channel);
+                Ok(channel)
+            },
+            None => Err("im.open returned no channel".to_owned()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::DirectMessages;
+
+    #[test]
+    fn test_remembered_ims() {
+        let mut ims = DirectMessages::new("xoxb-test");
+        ims.remember("U023BECGF", "D024BE91L");
+
+        // Already open, so no request is made
+        assert_eq!(ims.channel("U023BECGF"), Ok("D024BE91L".to_owned()));
+    }
+}
@@ -4,7 +4,9 @@ use std::fmt;
 
 use rustc_serialize::json::{self, Json, ToJson};
 
-use message::OutgoingMessage;
+use message::{OutgoingMessage, Threading};
+
+use adapter::slack::directory::Directory;
 
 /// Data for an Event::Message(Msg::Plain)
 #[allow(dead_code)]
@@ -13,7 +15,8 @@ pub struct MessageData {
     channel: String,
     user: String,
     ts: String,
-    team: String
+    team: String,
+    thread_ts: Option<String>
 }
 
 macro_rules! str_accessor {
@@ -34,8 +37,13 @@ impl MessageData {
     str_accessor!(user);
     /// When the message was sent
     str_accessor!(ts);
-    /// Team owning the message
+    /// Team owning the message. Empty when Slack doesn't say, as for edits.
     str_accessor!(team);
+
+    /// When the thread the message is in was started, if it is in one
+    pub fn thread_ts(&self) -> Option<&str> {
+        self.thread_ts.as_ref().map(|ts| ts.as_ref())
+    }
 }
 
 /// Incoming slack messages on the websocket api
@@ -54,6 +62,26 @@ pub enum Msg {
     /// A regular text message from a user
     Plain(MessageData),
 
+    /// A user described something they did with `/me`
+    Me(MessageData),
+
+    /// A message was edited. Holds the message as it reads now.
+    Changed(MessageData),
+
+    /// A message was deleted
+    Deleted {
+        channel: String,
+        /// When the deleted message was sent
+        ts: String,
+    },
+
+    /// A message from an integration or another bot rather than a user
+    Bot {
+        channel: String,
+        bot_id: String,
+        text: String,
+    },
+
     /// Subtype not explicitly handled. For a complete enumeration, check
     /// https://api.slack.com/events/message
     Other(json::Object)
@@ -122,32 +150,115 @@ macro_rules! get_json_string {
     }
 }
 
+/// Extract an optional String ($key) from a json::Object ($obj)
+/// Returns an Err(EventDecodingError) when it is present but not a string
+macro_rules! get_optional_json_string {
+    ($obj:ident, $key:expr) => {
+        match $obj.get($key) {
+            Some(json_str) => match json_str.as_string() {
+                Some(slice) => Some(slice.to_owned()),
+                None => {
+                    return Err(EventDecodingError::WrongType($key.to_owned(), "string".to_owned()))
+                }
+            },
+            None => None
+        }
+    }
+}
+
 /// Decode the Event::Message data
 fn decode_msg_json(obj: json::Object) -> Result<Event, EventDecodingError> {
-    // Messages with a `subtype` are not plain text messages.. Not interested in them for now.
-    if obj.contains_key("subtype") {
-        return Ok(Event::Message(Msg::Other(obj)))
-    }
+    let subtype = match get_optional_json_string!(obj, "subtype") {
+        Some(subtype) => subtype,
+        // Apps can post plain messages too, but say which bot they are
+        None if obj.contains_key("bot_id") => {
+            return Ok(Event::Message(Msg::Bot {
+                channel: get_json_string!(obj, "channel"),
+                bot_id: get_json_string!(obj, "bot_id"),
+                text: get_optional_json_string!(obj, "text").unwrap_or_default(),
+            }))
+        },
+        // It's a plain message.
+        None => {
+            return Ok(Event::Message(Msg::Plain(MessageData {
+                text: get_json_string!(obj, "text"),
+                channel: get_json_string!(obj, "channel"),
+                user: get_json_string!(obj, "user"),
+                ts: get_json_string!(obj, "ts"),
+                team: get_optional_json_string!(obj, "team").unwrap_or_default(),
+                thread_ts: get_optional_json_string!(obj, "thread_ts"),
+            })))
+        }
+    };
+
+    let msg = match subtype.as_ref() {
+        "me_message" if obj.contains_key("bot_id") => Msg::Bot {
+            channel: get_json_string!(obj, "channel"),
+            bot_id: get_json_string!(obj, "bot_id"),
+            text: get_optional_json_string!(obj, "text").unwrap_or_default(),
+        },
+        "me_message" => Msg::Me(MessageData {
+            text: get_json_string!(obj, "text"),
+            channel: get_json_string!(obj, "channel"),
+            user: get_json_string!(obj, "user"),
+            ts: get_json_string!(obj, "ts"),
+            team: get_optional_json_string!(obj, "team").unwrap_or_default(),
+            thread_ts: get_optional_json_string!(obj, "thread_ts"),
+        }),
+        "message_changed" => {
+            let message = match obj.get("message").and_then(|message| message.as_object()) {
+                Some(message) => message,
+                None => {
+                    return Err(EventDecodingError::MissingField("message".to_owned()))
+                }
+            };
+
+            // An app editing one of its messages
+            if message.contains_key("bot_id") {
+                return Ok(Event::Message(Msg::Bot {
+                    channel: get_json_string!(obj, "channel"),
+                    bot_id: get_json_string!(message, "bot_id"),
+                    text: get_optional_json_string!(message, "text").unwrap_or_default(),
+                }))
+            }
 
-    // It's a plain message.
-    Ok(Event::Message(Msg::Plain(MessageData {
-        text: get_json_string!(obj, "text"),
-        channel: get_json_string!(obj, "channel"),
-        user: get_json_string!(obj, "user"),
-        ts: get_json_string!(obj, "ts"),
-        team: get_json_string!(obj, "team"),
-    })))
+            Msg::Changed(MessageData {
+                text: get_json_string!(message, "text"),
+                channel: get_json_string!(obj, "channel"),
+                user: get_json_string!(message, "user"),
+                ts: get_json_string!(message, "ts"),
+                team: get_optional_json_string!(message, "team").unwrap_or_default(),
+                thread_ts: get_optional_json_string!(message, "thread_ts"),
+            })
+        },
+        "message_deleted" => Msg::Deleted {
+            channel: get_json_string!(obj, "channel"),
+            ts: get_json_string!(obj, "deleted_ts"),
+        },
+        "bot_message" => Msg::Bot {
+            channel: get_json_string!(obj, "channel"),
+            bot_id: get_json_string!(obj, "bot_id"),
+            text: get_optional_json_string!(obj, "text").unwrap_or_default(),
+        },
+        _ => Msg::Other(obj),
+    };
+
+    Ok(Event::Message(msg))
 }
 
 /// Convert a JSON string to a Event
 pub fn string_to_slack_msg(raw: &str) -> Result<Event, EventDecodingError> {
     // The message should be a string representation of a JSON object
     let json = try!(Json::from_str(raw));
-    let obj = match json {
-        Json::Object(obj) => obj,
-        _ => return Err(EventDecodingError::WrongType("root".to_owned(), "object".to_owned()))
-    };
+    match json {
+        Json::Object(obj) => decode_event(obj),
+        _ => Err(EventDecodingError::WrongType("root".to_owned(), "object".to_owned()))
+    }
+}
 
+/// Convert a decoded JSON object to a Event, such as the `event` in an Events API callback. The
+/// RTM client decodes the socket's events itself, so SlackAdapter doesn't use this.
+pub fn decode_event(obj: json::Object) -> Result<Event, EventDecodingError> {
     // If the message does not have a `type`, it is a confirmation message
     if !obj.contains_key("type") {
         return Ok(Event::Other(obj))
@@ -171,23 +282,89 @@ pub fn string_to_slack_msg(raw: &str) -> Result<Event, EventDecodingError> {
 /// An outgoing slack event.
 ///
 /// The OutgoingEvent type only supports type = "message" at this time. The `type` field is recorded
-/// in the struct as `msg_type` since rust has a conflicting `type` keyword.
+/// in the struct as `msg_type` since rust has a conflicting `type` keyword. The `id` Slack uses to
+/// acknowledge the event is set with `set_id` once it is known which connection it goes out on.
 #[derive(Debug)]
 pub struct OutgoingEvent {
     id: i64,
     channel: String,
     msg_type: String,
-    text: String
+    text: String,
+    action: bool,
+    thread_ts: Option<String>,
+    reply_broadcast: bool
 }
 
 impl OutgoingEvent {
-    pub fn new(id: i64, m: OutgoingMessage) -> OutgoingEvent {
+    pub fn new(m: OutgoingMessage) -> OutgoingEvent {
+        let channel = m.get_incoming().channel().expect("missing channel").to_owned();
+        let thread_ts = m.thread().map(|ts| ts.to_owned());
+        let reply_broadcast = m.threading() == Threading::Broadcast;
+
         OutgoingEvent {
-            id: id,
-            channel: m.get_incoming().channel().expect("missing channel").to_owned(),
+            thread_ts: thread_ts,
+            reply_broadcast: reply_broadcast,
+            .. OutgoingEvent::reply_in(channel, m)
+        }
+    }
+
+    /// A reply sent to `channel` rather than where the incoming message came from, such as the
+    /// IM channel with the user for a private reply. It isn't threaded.
+    pub fn reply_in(channel: String, m: OutgoingMessage) -> OutgoingEvent {
+        OutgoingEvent {
+            id: 0,
+            channel: channel,
+            msg_type: "message".to_owned(),
+            text: m.as_ref().to_owned(), // TODO move instead of copy
+            action: m.is_action(),
+            thread_ts: None,
+            reply_broadcast: false
+        }
+    }
+
+    /// An event which is not a reply to anything. `channel` must be a Slack channel ID.
+    pub fn to_channel(channel: String, text: String) -> OutgoingEvent {
+        OutgoingEvent {
+            id: 0,
+            channel: channel,
             msg_type: "message".to_owned(),
-            text: m.as_ref().to_owned() // TODO move instead of copy
+            text: text,
+            action: false,
+            thread_ts: None,
+            reply_broadcast: false
+        }
+    }
+
+    /// Whether the event is an action, which the Web API sends with `chat.meMessage`
+    pub fn is_action(&self) -> bool {
+        self.action
+    }
+
+    /// The parameters for posting the event with the Web API's `chat.postMessage`, which takes
+    /// the same fields as the RTM socket, or `chat.meMessage` for an action. `chat.meMessage`
+    /// can't reply in a thread, so an action is posted to the channel.
+    pub fn post_params(&self) -> Vec<(&'static str, String)> {
+        let mut params = vec![("channel", self.channel.clone()), ("text", self.text.clone())];
+        if self.action {
+            return params;
+        }
+        if let Some(ref thread_ts) = self.thread_ts {
+            params.push(("thread_ts", thread_ts.clone()));
+            if self.reply_broadcast {
+                params.push(("reply_broadcast", "true".to_owned()));
+            }
         }
+        params
+    }
+
+    /// Number the event for the connection it is about to be sent on
+    pub fn set_id(&mut self, id: i64) {
+        self.id = id;
+    }
+
+    /// Turn `@name` in the text into a mention of each user `directory` knows
+    pub fn link_mentions(&mut self, directory: &Directory) {
+        self.text = directory.mentions(&self.text);
     }
 }
 
@@ -197,7 +374,19 @@ impl ToJson for OutgoingEvent {
         d.insert("id".to_string(), self.id.to_json());
         d.insert("channel".to_string(), self.channel.to_json());
         d.insert("type".to_string(), self.msg_type.to_json());
-        d.insert("text".to_string(), self.text.to_json());
+        // The RTM API can't send `me_message`s. Actions go out as ordinary messages in italics,
+        // which is how Slack shows a `me_message`, but other clients and bots see a plain message.
+        if self.action {
+            d.insert("text".to_string(), format!("_{}_", self.text).to_json());
+        } else {
+            d.insert("text".to_string(), self.text.to_json());
+        }
+        if let Some(ref thread_ts) = self.thread_ts {
+            d.insert("thread_ts".to_string(), thread_ts.to_json());
+            if self.reply_broadcast {
+                d.insert("reply_broadcast".to_string(), true.to_json());
+            }
+        }
         Json::Object(d)
     }
 }
@@ -207,6 +396,7 @@ mod tests {
     use adapter::slack::message::Event;
     use adapter::slack::message::Msg;
     use adapter::slack::message::string_to_slack_msg;
+    use adapter::slack::message::OutgoingEvent;
 
     #[test]
     fn decode_message() {
@@ -272,8 +462,13 @@ mod tests {
         }"#;
 
         match string_to_slack_msg(raw).unwrap() {
-            Event::Message(Msg::Other(_)) => return,
-            _ => panic!("expected Msg::Other")
+            Event::Message(Msg::Changed(data)) => {
+                assert_eq!(data.text(), "arst");
+                assert_eq!(data.channel(), "D04UYUAMW");
+                assert_eq!(data.user(), "U02ALMR84");
+                assert_eq!(data.ts(), "1432695812.000056");
+            },
+            _ => panic!("expected Msg::Changed")
         }
     }
 
@@ -289,8 +484,8 @@ mod tests {
         }"#;
 
         match string_to_slack_msg(raw).unwrap() {
-            Event::Message(Msg::Other(_)) => return,
-            _ => panic!("expected Msg::Other")
+            Event::Message(Msg::Me(data)) => assert_eq!(data.text(), "is a potato"),
+            _ => panic!("expected Msg::Me")
         }
     }
 
@@ -306,10 +501,158 @@ mod tests {
             "ts":"1432695848.000061"
         }"#;
 
+        match string_to_slack_msg(raw).unwrap() {
+            Event::Message(Msg::Deleted { channel, ts }) => {
+                assert_eq!(channel, "D04UYUAMW");
+                assert_eq!(ts, "1432695826.000060");
+            },
+            _ => panic!("expected Msg::Deleted")
+        }
+    }
+
+    #[test]
+    fn decode_bot_message() {
+        let raw = r#"{
+            "type":"message",
+            "subtype":"bot_message",
+            "ts":"1358877455.000010",
+            "channel":"C024BE91L",
+            "text":"Pushing is the answer",
+            "bot_id":"BB12033",
+            "username":"github"
+        }"#;
+
+        match string_to_slack_msg(raw).unwrap() {
+            Event::Message(Msg::Bot { bot_id, text, .. }) => {
+                assert_eq!(bot_id, "BB12033");
+                assert_eq!(text, "Pushing is the answer");
+            },
+            _ => panic!("expected Msg::Bot")
+        }
+    }
+
+    #[test]
+    fn decode_app_message() {
+        // The Events API sends apps' messages without a subtype
+        let raw = r#"{
+            "type":"message",
+            "ts":"1358877456.000010",
+            "channel":"C024BE91L",
+            "text":"Deployed",
+            "bot_id":"BB12033",
+            "thread_ts":"1358877455.000010"
+        }"#;
+
+        match string_to_slack_msg(raw).unwrap() {
+            Event::Message(Msg::Bot { bot_id, .. }) => assert_eq!(bot_id, "BB12033"),
+            _ => panic!("expected Msg::Bot")
+        }
+    }
+
+    #[test]
+    fn decode_app_me_message_and_edit() {
+        // Apps' messages are never handled, whatever their subtype
+        let me = r#"{
+            "type":"message",
+            "subtype":"me_message",
+            "ts":"1358877457.000010",
+            "channel":"C024BE91L",
+            "user":"U0BOTUSER",
+            "text":"deploys",
+            "bot_id":"BB12033"
+        }"#;
+
+        match string_to_slack_msg(me).unwrap() {
+            Event::Message(Msg::Bot { bot_id, text, .. }) => {
+                assert_eq!(bot_id, "BB12033");
+                assert_eq!(text, "deploys");
+            },
+            _ => panic!("expected Msg::Bot")
+        }
+
+        let changed = r#"{
+            "type":"message",
+            "subtype":"message_changed",
+            "channel":"C024BE91L",
+            "message":{
+                "type":"message",
+                "user":"U0BOTUSER",
+                "bot_id":"BB12033",
+                "text":"Deployed again",
+                "ts":"1358877456.000010"
+            },
+            "ts":"1358877459.000012"
+        }"#;
+
+        match string_to_slack_msg(changed).unwrap() {
+            Event::Message(Msg::Bot { channel, text, .. }) => {
+                assert_eq!(channel, "C024BE91L");
+                assert_eq!(text, "Deployed again");
+            },
+            _ => panic!("expected Msg::Bot")
+        }
+    }
+
+    #[test]
+    fn decode_other_subtype() {
+        let raw = r#"{
+            "type":"message",
+            "subtype":"channel_join",
+            "ts":"1358877458.000011",
+            "channel":"C024BE91L",
+            "user":"U2147483828",
+            "text":"<@U2147483828|cal> has joined the channel"
+        }"#;
+
         match string_to_slack_msg(raw).unwrap() {
             Event::Message(Msg::Other(_)) => return,
             _ => panic!("expected Msg::Other")
         }
     }
 
+    #[test]
+    fn encode_threaded_reply() {
+        use std::sync::mpsc::channel;
+
+        use rustc_serialize::json::{Json, ToJson};
+
+        use message::{IncomingMessage, OutgoingMessage, Threading};
+
+        let (tx, _rx) = channel();
+        let mut incoming = IncomingMessage::new("SlackAdapter".to_owned(), None,
+            Some("C024BE91L".to_owned()), Some("U02ALMR84".to_owned()), "ping".to_owned(), tx);
+        incoming.set_id(Some("1432563914.000007".to_owned()));
+
+        // Not in a thread, so an ordinary reply isn't either
+        let reply = OutgoingEvent::new(OutgoingMessage::new("pong".to_owned(),
+                                                            incoming.clone())).to_json();
+        assert_eq!(reply.find("thread_ts"), None);
+
+        // A broadcast reply starts a thread from the message
+        let broadcast = OutgoingMessage::new("pong".to_owned(), incoming)
+            .with_threading(Threading::Broadcast);
+        let reply = OutgoingEvent::new(broadcast).to_json();
+        assert_eq!(reply.find("thread_ts"), Some(&Json::String("1432563914.000007".to_owned())));
+        assert_eq!(reply.find("reply_broadcast"), Some(&Json::Boolean(true)));
+    }
+
+    #[test]
+    fn encode_action() {
+        use std::sync::mpsc::channel;
+
+        use rustc_serialize::json::{Json, ToJson};
+
+        use message::{IncomingMessage, OutgoingMessage};
+
+        let (tx, _rx) = channel();
+        let incoming = IncomingMessage::new("SlackAdapter".to_owned(), None,
+            Some("C024BE91L".to_owned()), Some("U02ALMR84".to_owned()), "ping".to_owned(), tx);
+        let action = OutgoingEvent::new(OutgoingMessage::action("waves".to_owned(), incoming));
+
+        // The Web API sends the text as a `me_message`; the RTM socket can only italicise it
+        assert!(action.is_action());
+        assert_eq!(action.post_params(), vec![("channel", "C024BE91L".to_owned()),
+                                              ("text", "waves".to_owned())]);
+        assert_eq!(action.to_json().find("text"), Some(&Json::String("_waves_".to_owned())));
+    }
 }
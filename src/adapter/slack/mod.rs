@@ -1,43 +1,189 @@
+mod directory;
+use self::directory::Directory;
+
+mod events;
+pub use self::events::{EventsConfig, EventsError, SlackEventsAdapter};
+
+mod http;
+
+mod im;
+use self::im::DirectMessages;
+
 mod message;
 use self::message::*;
 
-use std::env;
-use std::sync::mpsc::{Sender, channel};
-use std::thread;
+mod outbox;
+use self::outbox::{Connection, Outbox};
+
+mod signature;
 
-use rustc_serialize::json::ToJson;
+use std::cmp;
+use std::env;
+use std::sync::{Arc, RwLock};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::mpsc::{channel, Receiver, RecvTimeoutError, Sender};
+use std::thread::{self, JoinHandle};
+use std::time::Duration;
 
 use slack;
 use regex::Regex;
 
-use adapter::ChatAdapter;
+use adapter::{strip_address, ChatAdapter};
 use message::AdapterMsg;
 use message::IncomingMessage;
+use message::Target;
 
-/// SlackAdapter sends and receives messages from the Slack chat service. Until actualy
-/// configuration is added, the slack token should be placed in the environment variable
-/// `SLACK_BOT_TOKEN`
+/// Shortest wait before logging in again after the connection drops
+const RECONNECT_MIN_SECS: u64 = 1;
+
+/// Longest wait between attempts to log in again
+const RECONNECT_MAX_SECS: u64 = 300;
+
+/// How often held messages are retried while disconnected
+const OUTGOING_POLL_MS: u64 = 500;
+
+/// SlackAdapter sends and receives messages from the Slack chat service. The token is either passed
+/// to [`login`](#method.login), read from the `SLACK_BOT_TOKEN` environment variable by `new`, or
+/// given in a [config file](../config/index.html).
+///
+/// Users and channels are given by ID, with their names available from
+/// [`user_name`](../message/struct.IncomingMessage.html#method.user_name) and `channel_name`.
+/// Mentions in messages are shown to handlers as `@name` and `#channel`, and `@name` in a reply
+/// mentions that user.
+///
+/// Replies to messages in a thread stay in the thread, and
+/// [`reply_in_thread`](../message/struct.IncomingMessage.html#method.reply_in_thread) starts one.
+///
+/// Private replies are sent in the user's direct message channel, which is opened if the bot
+/// hasn't talked to them before.
+///
+/// The bot is addressed by mentioning it anywhere in a message, or by any message in a direct
+/// message channel.
+///
+/// Slack closes the RTM socket from time to time. The adapter then logs in again, waiting longer
+/// after each failed attempt, and holds replies until it is connected.
+///
+/// Messages from other bots and integrations, and the bot's own messages, never reach handlers,
+/// so two bots can't keep answering each other. Edited messages are only handled again when
+/// enabled with [`with_edits`](#method.with_edits).
+///
+/// The RTM API can't send actions, so actions from the bot are sent as ordinary messages in
+/// italics. They look like actions in Slack but aren't marked as them.
+///
+/// SlackAdapter uses the legacy RTM API. [`SlackEventsAdapter`](struct.SlackEventsAdapter.html)
+/// receives events over HTTP from the Events API instead.
 pub struct SlackAdapter {
     client: Option<slack::RtmClient>,
-    addresser_regex: Regex
+    token: String,
+    id: String,
+    edits: bool,
+    addresser_regex: Regex,
+    stopping: Arc<AtomicBool>,
+    tx_outgoing: Option<Sender<AdapterMsg>>,
+    sender_thread: Option<JoinHandle<()>>,
 }
 
 impl SlackAdapter {
+    /// Log in with the token in the `SLACK_BOT_TOKEN` environment variable. Panics if the variable
+    /// is missing or the login fails; use [`login`](#method.login) to handle those errors.
     pub fn new(bot_name: &str) -> SlackAdapter {
         let token = env::var("SLACK_BOT_TOKEN").expect("Failed to get SLACK_BOT_TOKEN from env");
-        let cli = slack::RtmClient::login(&token[..]).expect("login to slack");
+        SlackAdapter::login(bot_name, &token).expect("login to slack")
+    }
+
+    /// Log in to Slack with `token`. The bot's user ID, which mentions of it contain, is learned
+    /// here.
+    pub fn login(bot_name: &str, token: &str) -> Result<SlackAdapter, slack::Error> {
+        let cli = try!(slack::RtmClient::login(token));
+        let id = match cli.start_response().slf.as_ref().and_then(|slf| slf.id.as_ref()) {
+            Some(id) => id.to_owned(),
+            None => {
+                let msg = format!("rtm.start didn't give the user ID for {}", bot_name);
+                return Err(slack::Error::Api(msg));
+            }
+        };
 
-        SlackAdapter {
+        Ok(SlackAdapter {
             client: Some(cli),
-            addresser_regex: Regex::new(format!(r"^<@{}>", bot_name).as_str()).unwrap()
-        }
+            token: token.to_owned(),
+            addresser_regex: mention_regex(&id),
+            id: id,
+            edits: false,
+            stopping: Arc::new(AtomicBool::new(false)),
+            tx_outgoing: None,
+            sender_thread: None,
+        })
+    }
+
+    /// Run handlers again on a message when a user edits it. Off by default, since handlers
+    /// usually reply a second time.
+    pub fn with_edits(mut self, edits: bool) -> SlackAdapter {
+        self.edits = edits;
+        self
     }
 }
 
+/// Matches a mention of the user with `id`, and the colon often typed after it
+fn mention_regex(id: &str) -> Regex {
+    Regex::new(&format!(r"<@{}(\|[^>]*)?>:?", id)).unwrap()
+}
+
+/// Direct message channel IDs start with D
+fn is_direct(channel: &str) -> bool {
+    channel.starts_with('D')
+}
+
+/// An IncomingMessage for `adapter` with readable text and names from `directory`. Whether the
+/// bot was addressed is decided here, since `mention` matches the bot's ID before it is made
+/// readable.
+fn to_incoming(adapter: &str, directory: &Directory, mention: &Regex, channel: Option<String>,
+               user: Option<String>, text: String,
+               tx_outgoing: Sender<AdapterMsg>) -> IncomingMessage {
+    let channel_name = channel.as_ref().and_then(|chan| directory.channel_name(chan))
+                              .map(|name| name.to_owned());
+    let user_name = user.as_ref().and_then(|user| directory.user_name(user))
+                        .map(|name| name.to_owned());
+
+    let contents = directory.readable(&text);
+    let addressed = if channel.as_ref().map_or(false, |chan| is_direct(chan)) {
+        Some(contents.clone())
+    } else {
+        strip_address(mention, &text).map(|rest| directory.readable(&rest))
+    };
+
+    let mut incoming = IncomingMessage::new(adapter.to_owned(), None, channel, user, contents,
+                                            tx_outgoing);
+    incoming.set_channel_name(channel_name);
+    incoming.set_user_name(user_name);
+    incoming.set_addressed(addressed);
+    incoming
+}
+
 struct MyHandler {
   count: i64,
+  self_id: String,
+  edits: bool,
+  mention: Regex,
   tx_incoming: Sender<IncomingMessage>,
   tx_outgoing: Sender<AdapterMsg>,
+  directory: Arc<RwLock<Directory>>,
+  conn: Connection<slack::Sender>,
+  stopping: Arc<AtomicBool>,
+}
+
+impl MyHandler {
+    /// An IncomingMessage with readable text and names from the directory
+    fn incoming(&self, channel: Option<String>, user: Option<String>,
+                text: String) -> IncomingMessage {
+        to_incoming("SlackAdapter", &self.directory.read().unwrap(), &self.mention, channel, user,
+                    text, self.tx_outgoing.clone())
+    }
+
+    /// Whether a message from `user` should reach handlers. Messages with no user come from
+    /// integrations, and the bot's own messages are echoed back to it.
+    fn from_person(&self, user: Option<&String>) -> bool {
+        user.map_or(false, |user| *user != self.self_id)
+    }
 }
 
 #[allow(unused_variables)]
@@ -49,21 +195,225 @@ impl slack::EventHandler for MyHandler {
         println!("Received[{}]: {:?}", self.count, event);
         self.count = self.count + 1;
 
-        if let slack::Event::Message(msg) = event {
-            if let slack::Message::Standard(msg) = *msg {
-                let incoming = IncomingMessage::new("SlackAdapter".to_owned(), None,
-                    msg.channel, msg.user,
-                    msg.text.unwrap(), self.tx_outgoing.clone());
+        let msg = match event {
+            slack::Event::Message(msg) => msg,
+            slack::Event::UserChange { ref user } | slack::Event::TeamJoin { ref user } => {
+                self.directory.write().unwrap().add_user(user);
+                return
+            },
+            slack::Event::ChannelCreated { ref channel } |
+            slack::Event::ChannelJoined { ref channel } |
+            slack::Event::ChannelRename { ref channel } |
+            slack::Event::GroupJoined { ref channel } |
+            slack::Event::GroupRename { ref channel } => {
+                self.directory.write().unwrap().add_channel(channel);
+                return
+            },
+            _ => return
+        };
+
+        let incoming = match *msg {
+            slack::Message::Standard(msg) => {
+                // Bots posting as a user still say which bot they are
+                if !self.from_person(msg.user.as_ref()) || msg.bot_id.is_some() {
+                    return
+                }
+
+                let mut incoming = self.incoming(msg.channel, msg.user,
+                                                 msg.text.unwrap_or_default());
+                incoming.set_id(msg.ts);
+                incoming.set_thread(msg.thread_ts);
+                incoming
+            },
+            slack::Message::MeMessage(msg) => {
+                // Slack's me_message doesn't say whether a bot sent it, so only the bot's own
+                // are skipped
+                if !self.from_person(msg.user.as_ref()) {
+                    return
+                }
+
+                let mut incoming = self.incoming(msg.channel, msg.user,
+                                                 msg.text.unwrap_or_default());
+                incoming.set_id(msg.ts);
+                incoming.set_action(true);
+                incoming
+            },
+            slack::Message::MessageChanged(changed) => {
+                let edited = match changed.message {
+                    Some(edited) => edited,
+                    None => return
+                };
+                let from_bot = !self.from_person(edited.user.as_ref()) || edited.bot_id.is_some();
+                if !self.edits || from_bot {
+                    return
+                }
+
+                let mut incoming = self.incoming(changed.channel, edited.user,
+                                                 edited.text.unwrap_or_default());
+                incoming.set_id(edited.ts);
+                incoming.set_thread(edited.thread_ts);
+                incoming
+            },
+            // bot_message, deletions, joins and the like aren't for handlers
+            _ => return
+        };
+
+        if let Err(e) = self.tx_incoming.send(incoming) {
+            // The chatbot has gone, so there's no one to pass messages to
+            println!("SlackAdapter: chatbot not receiving messages: {}; disconnecting", e);
+            self.stopping.store(true, Ordering::SeqCst);
+            if let Err(e) = cli.sender().shutdown() {
+                println!("SlackAdapter: failed to close socket: {:?}", e);
+            }
+        }
+    }
+
+    /// The socket is closing, so replies are held until the next connection
+    fn on_close(&mut self, cli: &slack::RtmClient) {
+        self.conn.disconnected();
+    }
+
+    fn on_connect(&mut self, cli: &slack::RtmClient) {
+        println!("SlackAdapter: connected");
+        self.conn.connected(cli.sender().clone());
+    }
+}
+
+/// Sleep for `delay`, waking early if the adapter is stopping
+fn backoff(delay: Duration, stopping: &AtomicBool) {
+    let step = Duration::from_millis(100);
+    let mut slept = Duration::from_millis(0);
+
+    while slept < delay && !stopping.load(Ordering::SeqCst) {
+        thread::sleep(step);
+        slept += step;
+    }
+}
+
+/// Run `cli` until the adapter stops, logging in again with `token` whenever the socket drops
+fn maintain_connection(mut cli: slack::RtmClient, token: String, mut handler: MyHandler) {
+    let stopping = handler.stopping.clone();
+    let mut delay = Duration::from_secs(RECONNECT_MIN_SECS);
 
-                self.tx_incoming.send(incoming)
-                                .ok().expect("Bot unable to process messages");
+    while !stopping.load(Ordering::SeqCst) {
+        let res = cli.run(&mut handler);
+        // A read error ends `run` without calling `on_close`
+        handler.conn.disconnected();
+
+        if stopping.load(Ordering::SeqCst) {
+            break;
+        }
+
+        match res {
+            Ok(()) => println!("SlackAdapter: disconnected; retrying in {}s", delay.as_secs()),
+            Err(e) => {
+                println!("SlackAdapter: connection lost: {:?}; retrying in {}s", e,
+                         delay.as_secs());
             }
         }
+
+        loop {
+            backoff(delay, &stopping);
+            delay = cmp::min(delay * 2, Duration::from_secs(RECONNECT_MAX_SECS));
+
+            if stopping.load(Ordering::SeqCst) {
+                return;
+            }
+
+            match slack::RtmClient::login(&token) {
+                Ok(new_cli) => {
+                    cli = new_cli;
+                    break;
+                },
+                Err(e) => {
+                    println!("SlackAdapter: failed to log in: {:?}; retrying in {}s", e,
+                             delay.as_secs());
+                }
+            }
+        }
+
+        // Users and channels may have changed while the bot was away
+        *handler.directory.write().unwrap() = Directory::load(cli.start_response());
+        delay = Duration::from_secs(RECONNECT_MIN_SECS);
     }
+}
+
+/// Turn a message from the bot into an event for `adapter` to send, finding the channel for
+/// private messages to a user with `im_channel`. Returns `None` for messages which can't be sent.
+fn to_event<F>(adapter: &str, msg: AdapterMsg, mut im_channel: F) -> Option<OutgoingEvent>
+    where F: FnMut(&str) -> Result<String, String>
+{
+    match msg {
+        AdapterMsg::Outgoing(m) => Some(OutgoingEvent::new(m)),
+        AdapterMsg::Proactive(Target::Channel(channel), text) => {
+            Some(OutgoingEvent::to_channel(channel, text))
+        },
+        AdapterMsg::Private(m) => {
+            let user = match m.get_incoming().user() {
+                Some(user) => user.to_owned(),
+                None => {
+                    println!("{}: no user to reply to privately", adapter);
+                    return None
+                }
+            };
+
+            match im_channel(&user) {
+                Ok(channel) => Some(OutgoingEvent::reply_in(channel, m)),
+                Err(e) => {
+                    println!("{}: failed to open IM with {}: {}", adapter, user, e);
+                    None
+                }
+            }
+        },
+        AdapterMsg::Proactive(Target::User(user), text) => {
+            match im_channel(&user) {
+                Ok(channel) => Some(OutgoingEvent::to_channel(channel, text)),
+                Err(e) => {
+                    println!("{}: failed to open IM with {}: {}", adapter, user, e);
+                    None
+                }
+            }
+        },
+        // Bot users can only be invited to channels and removed by others
+        AdapterMsg::Join(_) | AdapterMsg::Part(_) => {
+            println!("{}: bots can't join or part channels", adapter);
+            None
+        },
+        AdapterMsg::Shutdown => None,
+    }
+}
+
+/// Deliver messages from the bot, holding them while the connection is down
+fn deliver(rx_outgoing: Receiver<AdapterMsg>, conn: Connection<slack::Sender>,
+           directory: Arc<RwLock<Directory>>, mut ims: DirectMessages) {
+    let mut outbox = Outbox::new();
+
+    loop {
+        match rx_outgoing.recv_timeout(Duration::from_millis(OUTGOING_POLL_MS)) {
+            Ok(AdapterMsg::Shutdown) => {
+                conn.flush(&mut outbox);
+                if !outbox.is_empty() {
+                    println!("SlackAdapter: dropping {} undelivered messages", outbox.len());
+                }
 
-    fn on_close(&mut self, cli: &slack::RtmClient) { }
+                conn.close();
+                break
+            },
+            Ok(msg) => {
+                if let Some(mut out) = to_event("SlackAdapter", msg, |user| ims.channel(user)) {
+                    out.link_mentions(&directory.read().unwrap());
+                    outbox.hold(out);
+                }
+            },
+            Err(RecvTimeoutError::Timeout) => (),
+            Err(RecvTimeoutError::Disconnected) => {
+                println!("SlackAdapter: error receiving outgoing messages: channel closed");
+                break
+            }
+        }
 
-    fn on_connect(&mut self, cli: &slack::RtmClient) { }
+        conn.flush(&mut outbox);
+    }
 }
 
 impl ChatAdapter for SlackAdapter {
@@ -72,7 +422,8 @@ impl ChatAdapter for SlackAdapter {
         "SlackAdapter"
     }
 
-    /// Check whether this adapter was addressed
+    /// Matches a mention of the bot in a message as Slack sends it. The receiver thread uses it
+    /// before mentions are made readable and records the result on the message.
     fn addresser(&self) -> &Regex {
         &self.addresser_regex
     }
@@ -80,45 +431,74 @@ impl ChatAdapter for SlackAdapter {
     fn process_events(&mut self, tx_incoming: Sender<IncomingMessage>) {
         println!("SlackAdapter: process_events");
         let (tx_outgoing, rx_outgoing) = channel();
+        self.tx_outgoing = Some(tx_outgoing.clone());
 
         let cli = self.client.take().unwrap();
-        let slack_tx = cli.sender().clone();
+        let conn = Connection::new();
+        let directory = Arc::new(RwLock::new(Directory::load(cli.start_response())));
+
+        let mut ims = DirectMessages::new(&self.token);
+        for im in cli.start_response().ims.iter().flat_map(|ims| ims.iter()) {
+            if let (Some(user), Some(id)) = (im.user.as_ref(), im.id.as_ref()) {
+                ims.remember(user, id);
+            }
+        }
 
-        thread::Builder::new().name("Chatbot Slack Receiver".to_owned()).spawn(move || {
-            let mut handler = MyHandler {
+        {
+            let handler = MyHandler {
                 count: 0,
+                self_id: self.id.clone(),
+                edits: self.edits,
+                mention: self.addresser_regex.clone(),
                 tx_incoming: tx_incoming,
                 tx_outgoing: tx_outgoing,
+                directory: directory.clone(),
+                conn: conn.clone(),
+                stopping: self.stopping.clone(),
             };
-            cli.run(&mut handler).expect("run connector ok");
-        }).ok().expect("failed to create thread for slack receiver");
-
-        thread::Builder::new().name("Chatbot Slack Sender".to_owned()).spawn(move || {
-            loop {
-                match rx_outgoing.recv() {
-                    Ok(msg) => {
-                        match msg {
-                            AdapterMsg::Outgoing(m) => {
-                                let id = slack_tx.get_msg_uid() as i64;
-                                let out = OutgoingEvent::new(id, m);
-                                slack_tx.send(out.to_json().to_string().as_ref())
-                                        .expect("send message ok");
-                            }
-                            // Not implemented for now
-                            AdapterMsg::Private(_) => {
-                                println!("SlackAdaptor: Private messages not implemented");
-                            }
-                            AdapterMsg::Shutdown => {
-                                break
-                            }
-                        }
-                    },
-                    Err(e) => {
-                        println!("error receiving outgoing messages: {}", e);
-                        break
-                    }
-                }
-            }
+            let token = self.token.clone();
+            thread::Builder::new().name("Chatbot Slack Receiver".to_owned()).spawn(move || {
+                maintain_connection(cli, token, handler);
+            }).ok().expect("failed to create thread for slack receiver");
+        }
+
+        let sender = thread::Builder::new().name("Chatbot Slack Sender".to_owned()).spawn(move || {
+            deliver(rx_outgoing, conn, directory, ims);
         }).ok().expect("failed to create thread for slack sender");
+
+        self.sender_thread = Some(sender);
+    }
+
+    fn outgoing(&self) -> Option<Sender<AdapterMsg>> {
+        self.tx_outgoing.clone()
+    }
+
+    /// Close the RTM socket and wait for the sender thread to finish. The receiver thread exits on
+    /// its own once the client processes the close, without logging in again.
+    fn shutdown(&mut self) {
+        self.stopping.store(true, Ordering::SeqCst);
+
+        if let Some(tx) = self.tx_outgoing.take() {
+            let _ = tx.send(AdapterMsg::Shutdown);
+        }
+
+        if let Some(sender) = self.sender_thread.take() {
+            let _ = sender.join();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use adapter::strip_address;
+    use super::mention_regex;
+
+    #[test]
+    fn test_mention_anywhere() {
+        let mention = mention_regex("U0BOTID");
+
+        assert_eq!(strip_address(&mention, "<@U0BOTID>: ping"), Some("ping".to_owned()));
+        assert_eq!(strip_address(&mention, "hey <@U0BOTID|bot> ping"), Some("hey ping".to_owned()));
+        assert_eq!(strip_address(&mention, "hey <@U02ALMR84> ping"), None);
     }
 }
@@ -0,0 +1,237 @@
+//! Holding replies while the RTM socket is down, so they go out in order once the adapter has
+//! logged in again.
+
+use std::collections::VecDeque;
+use std::sync::{Arc, Mutex};
+
+use rustc_serialize::json::ToJson;
+
+use slack;
+
+use super::message::OutgoingEvent;
+
+/// Most outgoing messages held while disconnected
+const MAX_QUEUED: usize = 1000;
+
+/// Where events are sent, such as the sender for an RTM connection
+pub trait Socket {
+    /// The ID for the next event sent, which Slack uses to acknowledge it
+    fn next_id(&self) -> i64;
+
+    /// Send one event, encoded as JSON
+    fn send_json(&self, json: &str) -> Result<(), String>;
+
+    /// Close the socket
+    fn close(&self) -> Result<(), String>;
+}
+
+impl Socket for slack::Sender {
+    fn next_id(&self) -> i64 {
+        self.get_msg_uid() as i64
+    }
+
+    fn send_json(&self, json: &str) -> Result<(), String> {
+        self.send(json).map_err(|e| format!("{:?}", e))
+    }
+
+    fn close(&self) -> Result<(), String> {
+        self.shutdown().map_err(|e| format!("{:?}", e))
+    }
+}
+
+/// The socket for the current connection, if any, shared between the adapter's threads
+pub struct Connection<S> {
+    socket: Arc<Mutex<Option<S>>>,
+}
+
+impl<S> Clone for Connection<S> {
+    fn clone(&self) -> Connection<S> {
+        Connection { socket: self.socket.clone() }
+    }
+}
+
+impl<S: Socket> Connection<S> {
+    /// Not connected yet
+    pub fn new() -> Connection<S> {
+        Connection { socket: Arc::new(Mutex::new(None)) }
+    }
+
+    /// A connection has opened on `socket`, so events can be sent
+    pub fn connected(&self, socket: S) {
+        *self.socket.lock().unwrap() = Some(socket);
+    }
+
+    /// The connection has closed, so events are held until the next one
+    pub fn disconnected(&self) {
+        *self.socket.lock().unwrap() = None;
+    }
+
+    /// Send held events in order while connected. Events which fail to send stay held until the
+    /// next connection.
+    pub fn flush(&self, outbox: &mut Outbox) {
+        let socket = self.socket.lock().unwrap();
+        let socket = match *socket {
+            Some(ref socket) => socket,
+            None => return,
+        };
+
+        while let Some(mut out) = outbox.queue.pop_front() {
+            out.set_id(socket.next_id());
+
+            if let Err(e) = socket.send_json(out.to_json().to_string().as_ref()) {
+                println!("SlackAdapter: failed to send: {}; holding until reconnected", e);
+                outbox.queue.push_front(out);
+                return;
+            }
+        }
+    }
+
+    /// Close the socket if connected
+    pub fn close(&self) {
+        if let Some(ref socket) = *self.socket.lock().unwrap() {
+            if let Err(e) = socket.close() {
+                println!("SlackAdapter: failed to close socket: {}", e);
+            }
+        }
+    }
+}
+
+/// Events waiting to be sent, oldest first
+pub struct Outbox {
+    queue: VecDeque<OutgoingEvent>,
+}
+
+impl Outbox {
+    pub fn new() -> Outbox {
+        Outbox { queue: VecDeque::new() }
+    }
+
+    /// Hold `out` until it can be sent, dropping the oldest event if there are too many
+    pub fn hold(&mut self, out: OutgoingEvent) {
+        self.queue.push_back(out);
+
+        if self.queue.len() > MAX_QUEUED {
+            self.queue.pop_front();
+            println!("SlackAdapter: too many undelivered messages; dropped the oldest");
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.queue.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.queue.is_empty()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::cell::{Cell, RefCell};
+    use std::rc::Rc;
+
+    use rustc_serialize::json::Json;
+
+    use adapter::slack::message::OutgoingEvent;
+    use super::{Connection, Outbox, Socket, MAX_QUEUED};
+
+    /// Records what is sent, failing while `broken` is set
+    #[derive(Clone, Default)]
+    struct FakeSocket {
+        sent: Rc<RefCell<Vec<Json>>>,
+        broken: Rc<Cell<bool>>,
+    }
+
+    impl Socket for FakeSocket {
+        fn next_id(&self) -> i64 {
+            self.sent.borrow().len() as i64
+        }
+
+        fn send_json(&self, json: &str) -> Result<(), String> {
+            if self.broken.get() {
+                return Err("socket closed".to_owned());
+            }
+            self.sent.borrow_mut().push(Json::from_str(json).unwrap());
+            Ok(())
+        }
+
+        fn close(&self) -> Result<(), String> {
+            Ok(())
+        }
+    }
+
+    impl FakeSocket {
+        fn texts(&self) -> Vec<String> {
+            self.sent.borrow().iter().map(|json| {
+                json.find("text").and_then(|text| text.as_string()).unwrap().to_owned()
+            }).collect()
+        }
+    }
+
+    fn event(text: &str) -> OutgoingEvent {
+        OutgoingEvent::to_channel("C024BE91L".to_owned(), text.to_owned())
+    }
+
+    #[test]
+    fn test_held_until_connected() {
+        let conn = Connection::new();
+        let mut outbox = Outbox::new();
+        outbox.hold(event("one"));
+        outbox.hold(event("two"));
+
+        conn.flush(&mut outbox);
+        assert_eq!(outbox.len(), 2);
+
+        let socket = FakeSocket::default();
+        conn.connected(socket.clone());
+        conn.flush(&mut outbox);
+        assert!(outbox.is_empty());
+        assert_eq!(socket.texts(), vec!["one", "two"]);
+
+        // Each event is numbered for the connection it goes out on
+        let ids = socket.sent.borrow().iter().map(|json| json.find("id").unwrap().as_i64())
+                                          .collect::<Vec<_>>();
+        assert_eq!(ids, vec![Some(0), Some(1)]);
+
+        conn.disconnected();
+        outbox.hold(event("three"));
+        conn.flush(&mut outbox);
+        assert_eq!(outbox.len(), 1);
+        assert_eq!(socket.texts().len(), 2);
+    }
+
+    #[test]
+    fn test_failed_send_stays_held() {
+        let conn = Connection::new();
+        let socket = FakeSocket::default();
+        conn.connected(socket.clone());
+
+        let mut outbox = Outbox::new();
+        socket.broken.set(true);
+        outbox.hold(event("one"));
+        outbox.hold(event("two"));
+        conn.flush(&mut outbox);
+        assert_eq!(outbox.len(), 2);
+
+        // A new connection sends them in the original order
+        let socket = FakeSocket::default();
+        conn.connected(socket.clone());
+        conn.flush(&mut outbox);
+        assert_eq!(socket.texts(), vec!["one", "two"]);
+    }
+
+    #[test]
+    fn test_oldest_dropped() {
+        let conn = Connection::new();
+        let mut outbox = Outbox::new();
+        for i in 0..MAX_QUEUED + 1 {
+            outbox.hold(event(&i.to_string()));
+        }
+        assert_eq!(outbox.len(), MAX_QUEUED);
+
+        let socket = FakeSocket::default();
+        conn.connected(socket.clone());
+        conn.flush(&mut outbox);
+        assert_eq!(socket.texts()[0], "1");
+    }
+}
@@ -0,0 +1,155 @@
+//! Checking that Events API requests come from Slack. Each request is signed with an
+//! HMAC-SHA256 of its timestamp and body, keyed with the app's signing secret.
+
+/// Requests older than this are refused, so a captured request can't be replayed later
+const MAX_AGE_SECS: u64 = 5 * 60;
+
+/// Whether `signature`, from the `X-Slack-Signature` header, signs `body` sent at `timestamp`,
+/// from `X-Slack-Request-Timestamp`. `now` is the current Unix time in seconds.
+pub fn verify(secret: &str, timestamp: &str, body: &[u8], signature: &str, now: u64) -> bool {
+    let sent = match timestamp.parse::<u64>() {
+        Ok(sent) => sent,
+        Err(_) => return false,
+    };
+    if now.saturating_sub(sent) > MAX_AGE_SECS || sent.saturating_sub(now) > MAX_AGE_SECS {
+        return false;
+    }
+
+    let expected = sign(secret, timestamp, body);
+
+    // Compare every byte so the time taken doesn't say how much of a forgery was right
+    expected.len() == signature.len() &&
+        expected.bytes().zip(signature.bytes()).fold(0, |diff, (a, b)| diff | (a ^ b)) == 0
+}
+
+/// The `X-Slack-Signature` header for `body` sent at `timestamp`
+pub fn sign(secret: &str, timestamp: &str, body: &[u8]) -> String {
+    let mut message = format!("v0:{}:", timestamp).into_bytes();
+    message.extend_from_slice(body);
+    format!("v0={}", to_hex(&hmac_sha256(secret.as_bytes(), &message)))
+}
+
+fn to_hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|byte| format!("{:02x}", byte)).collect()
+}
+
+/// HMAC (RFC 2104) using SHA-256
+fn hmac_sha256(key: &[u8], message: &[u8]) -> [u8; 32] {
+    const BLOCK_BYTES: usize = 64;
+
+    let mut block = [0u8; BLOCK_BYTES];
+    if key.len() > BLOCK_BYTES {
+        block[..32].copy_from_slice(&sha256(key));
+    } else {
+        block[..key.len()].copy_from_slice(key);
+    }
+
+    let mut inner = block.iter().map(|b| b ^ 0x36).collect::<Vec<u8>>();
+    inner.extend_from_slice(message);
+    let mut outer = block.iter().map(|b| b ^ 0x5c).collect::<Vec<u8>>();
+    outer.extend_from_slice(&sha256(&inner));
+
+    sha256(&outer)
+}
+
+/// First 32 bits of the fractional parts of the cube roots of the first 64 primes
+const K: [u32; 64] = [
+    0x428a2f98, 0x71374491, 0xb5c0fbcf, 0xe9b5dba5, 0x3956c25b, 0x59f111f1, 0x923f82a4, 0xab1c5ed5,
+    0xd807aa98, 0x12835b01, 0x243185be, 0x550c7dc3, 0x72be5d74, 0x80deb1fe, 0x9bdc06a7, 0xc19bf174,
+    0xe49b69c1, 0xefbe4786, 0x0fc19dc6, 0x240ca1cc, 0x2de92c6f, 0x4a7484aa, 0x5cb0a9dc, 0x76f988da,
+    0x983e5152, 0xa831c66d, 0xb00327c8, 0xbf597fc7, 0xc6e00bf3, 0xd5a79147, 0x06ca6351, 0x14292967,
+    0x27b70a85, 0x2e1b2138, 0x4d2c6dfc, 0x53380d13, 0x650a7354, 0x766a0abb, 0x81c2c92e, 0x92722c85,
+    0xa2bfe8a1, 0xa81a664b, 0xc24b8b70, 0xc76c51a3, 0xd192e819, 0xd6990624, 0xf40e3585, 0x106aa070,
+    0x19a4c116, 0x1e376c08, 0x2748774c, 0x34b0bcb5, 0x391c0cb3, 0x4ed8aa4a, 0x5b9cca4f, 0x682e6ff3,
+    0x748f82ee, 0x78a5636f, 0x84c87814, 0x8cc70208, 0x90befffa, 0xa4506ceb, 0xbef9a3f7, 0xc67178f2,
+];
+
+/// SHA-256 (FIPS 180-4)
+fn sha256(data: &[u8]) -> [u8; 32] {
+    let mut h: [u32; 8] = [
+        0x6a09e667, 0xbb67ae85, 0x3c6ef372, 0xa54ff53a,
+        0x510e527f, 0x9b05688c, 0x1f83d9ab, 0x5be0cd19,
+    ];
+
+    // Pad with a 1 bit, zeros, and the length in bits to a multiple of 64 bytes
+    let mut padded = data.to_vec();
+    padded.push(0x80);
+    while padded.len() % 64 != 56 {
+        padded.push(0);
+    }
+    let bits = (data.len() as u64).wrapping_mul(8);
+    for i in (0..8).rev() {
+        padded.push((bits >> (i * 8)) as u8);
+    }
+
+    for chunk in padded.chunks(64) {
+        let mut w = [0u32; 64];
+        for i in 0..16 {
+            w[i] = (chunk[i * 4] as u32) << 24 | (chunk[i * 4 + 1] as u32) << 16 |
+                   (chunk[i * 4 + 2] as u32) << 8 | chunk[i * 4 + 3] as u32;
+        }
+        for i in 16..64 {
+            let s0 = w[i - 15].rotate_right(7) ^ w[i - 15].rotate_right(18) ^ (w[i - 15] >> 3);
+            let s1 = w[i - 2].rotate_right(17) ^ w[i - 2].rotate_right(19) ^ (w[i - 2] >> 10);
+            w[i] = w[i - 16].wrapping_add(s0).wrapping_add(w[i - 7]).wrapping_add(s1);
+        }
+
+        let mut v = h;
+        for i in 0..64 {
+            let s1 = v[4].rotate_right(6) ^ v[4].rotate_right(11) ^ v[4].rotate_right(25);
+            let ch = (v[4] & v[5]) ^ (!v[4] & v[6]);
+            let t1 = v[7].wrapping_add(s1).wrapping_add(ch).wrapping_add(K[i]).wrapping_add(w[i]);
+            let s0 = v[0].rotate_right(2) ^ v[0].rotate_right(13) ^ v[0].rotate_right(22);
+            let maj = (v[0] & v[1]) ^ (v[0] & v[2]) ^ (v[1] & v[2]);
+            let t2 = s0.wrapping_add(maj);
+
+            v = [t1.wrapping_add(t2), v[0], v[1], v[2], v[3].wrapping_add(t1), v[4], v[5], v[6]];
+        }
+
+        for i in 0..8 {
+            h[i] = h[i].wrapping_add(v[i]);
+        }
+    }
+
+    let mut digest = [0u8; 32];
+    for i in 0..8 {
+        for j in 0..4 {
+            digest[i * 4 + j] = (h[i] >> (24 - j * 8)) as u8;
+        }
+    }
+    digest
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{hmac_sha256, sha256, to_hex, verify};
+
+    #[test]
+    fn test_sha256() {
+        assert_eq!(to_hex(&sha256(b"")),
+                   "e3b0c44298fc1c149afbf4c8996fb92427ae41e4649b934ca495991b7852b855");
+        assert_eq!(to_hex(&sha256(b"abcdbcdecdefdefgefghfghighijhijkijkljklmklmnlmnomnopnopq")),
+                   "248d6a61d20638b8e5c026930c3e6039a33ce45964ff2167f6ecedd419db06c1");
+        assert_eq!(to_hex(&hmac_sha256(b"key", b"The quick brown fox jumps over the lazy dog")),
+                   "f7bc83f430538424b13298e6aa6fb143ef4d59a14946175997479dbc2d1a3cd8");
+    }
+
+    #[test]
+    fn test_verify() {
+        // The example from Slack's documentation
+        let secret = "8f742231b10e8888abcd99yyyzzz85a5";
+        let body = "token=xyzz0WbapA4vBCDEFasx0q6G&team_id=T1DC2JH3J&team_domain=testteamnow&\
+                    channel_id=G8PSS9T3V&channel_name=foobar&user_id=U2CERLKJA&\
+                    user_name=roadrunner&command=%2Fwebhook-collect&text=&response_url=\
+                    https%3A%2F%2Fhooks.slack.com%2Fcommands%2FT1DC2JH3J%2F397700885554%2F\
+                    96rGlfmibIGlgcZRskXaIFfN&trigger_id=398738663015.47445629121.\
+                    803a0bc887a14d10d2c447fce8b6703c";
+        let signature = "v0=a2114d57b48eac39b9ad189dd8316235a7b4a8d21a10bd27519666489c69b503";
+
+        assert!(verify(secret, "1531420618", body.as_bytes(), signature, 1531420618 + 60));
+        assert!(!verify(secret, "1531420618", b"tampered", signature, 1531420618));
+        assert!(!verify("wrong", "1531420618", body.as_bytes(), signature, 1531420618));
+        // Too old to trust
+        assert!(!verify(secret, "1531420618", body.as_bytes(), signature, 1531420618 + 3600));
+    }
+}
@@ -0,0 +1,375 @@
+//! Persistent key/value storage shared by all handlers.
+//!
+//! The Chatbot owns a single [`Brain`](trait.Brain.html). Each handler sees it through a
+//! [`Namespace`](struct.Namespace.html) named after the handler, available from
+//! [`IncomingMessage::brain`](../message/struct.IncomingMessage.html#method.brain), so handlers
+//! can't trample each other's keys. Values are strings; handlers wanting structured data can
+//! encode it as JSON.
+
+use std::collections::{BTreeMap, HashMap};
+use std::error::Error;
+use std::fmt;
+use std::fs::{self, File};
+use std::io::{self, Read, Write};
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+
+use rustc_serialize::json::{self, Json};
+
+/// Failure modes for a Brain
+#[derive(Debug)]
+pub enum BrainError {
+    /// Reading or writing the backing storage failed
+    Io(io::Error),
+    /// The stored data could not be parsed
+    Json(json::ParserError),
+    /// The stored data parsed but was not an object of strings
+    Format(String),
+}
+
+impl Error for BrainError {
+    fn description(&self) -> &str {
+        match *self {
+            BrainError::Io(ref err) => err.description(),
+            BrainError::Json(ref err) => err.description(),
+            BrainError::Format(_) => "Brain data is not an object of strings",
+        }
+    }
+
+    fn cause(&self) -> Option<&Error> {
+        match *self {
+            BrainError::Io(ref err) => Some(err),
+            BrainError::Json(ref err) => Some(err),
+            BrainError::Format(_) => None,
+        }
+    }
+}
+
+impl fmt::Display for BrainError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            BrainError::Io(ref err) => write!(f, "Io({})", err),
+            BrainError::Json(ref err) => write!(f, "Json({})", err),
+            BrainError::Format(ref what) => write!(f, "Format({})", what),
+        }
+    }
+}
+
+impl From<io::Error> for BrainError {
+    fn from(err: io::Error) -> BrainError {
+        BrainError::Io(err)
+    }
+}
+
+impl From<json::ParserError> for BrainError {
+    fn from(err: json::ParserError) -> BrainError {
+        BrainError::Json(err)
+    }
+}
+
+/// Storage for the bot's memories. Implementations are shared between handler threads and so
+/// must use interior mutability.
+pub trait Brain: Send + Sync {
+    /// Get the value stored under `key`
+    fn get(&self, key: &str) -> Option<String>;
+
+    /// Store `value` under `key`, replacing any previous value
+    fn set(&self, key: &str, value: String);
+
+    /// Remove `key`, returning its value if it had one
+    fn remove(&self, key: &str) -> Option<String>;
+
+    /// Every key currently stored
+    fn keys(&self) -> Vec<String>;
+
+    /// Write any pending changes to durable storage. The Chatbot calls this periodically and when
+    /// it shuts down. The default implementation does nothing.
+    fn flush(&self) -> Result<(), BrainError> {
+        Ok(())
+    }
+}
+
+/// A Brain which forgets everything when the bot exits. This is the Chatbot's default.
+pub struct MemoryBrain {
+    data: Mutex<HashMap<String, String>>,
+}
+
+impl MemoryBrain {
+    pub fn new() -> MemoryBrain {
+        MemoryBrain {
+            data: Mutex::new(HashMap::new())
+        }
+    }
+}
+
+impl Brain for MemoryBrain {
+    fn get(&self, key: &str) -> Option<String> {
+        self.data.lock().unwrap().get(key).cloned()
+    }
+
+    fn set(&self, key: &str, value: String) {
+        self.data.lock().unwrap().insert(key.to_owned(), value);
+    }
+
+    fn remove(&self, key: &str) -> Option<String> {
+        self.data.lock().unwrap().remove(key)
+    }
+
+    fn keys(&self) -> Vec<String> {
+        self.data.lock().unwrap().keys().cloned().collect()
+    }
+}
+
+/// Contents of a JsonFileBrain and whether they differ from what's on disk
+struct JsonData {
+    entries: BTreeMap<String, String>,
+    dirty: bool,
+}
+
+/// A Brain stored as a JSON object in a file
+///
+/// The file is read when the brain is opened and rewritten on `flush` if anything changed. Writes
+/// go to a temporary file next to the real one which is then renamed over it, so a crash during a
+/// flush doesn't lose the previous contents.
+pub struct JsonFileBrain {
+    path: PathBuf,
+    data: Mutex<JsonData>,
+}
+
+impl JsonFileBrain {
+    /// Open the brain stored at `path`. A missing file is treated as an empty brain and is created
+    /// on the first flush.
+    pub fn open<P: AsRef<Path>>(path: P) -> Result<JsonFileBrain, BrainError> {
+        let path = path.as_ref().to_path_buf();
+
+        let entries = match File::open(&path) {
+            Ok(mut file) => {
+                let mut raw = String::new();
+                try!(file.read_to_string(&mut raw));
+                try!(decode(&raw))
+            },
+            Err(ref err) if err.kind() == io::ErrorKind::NotFound => BTreeMap::new(),
+            Err(err) => return Err(BrainError::Io(err))
+        };
+
+        Ok(JsonFileBrain {
+            path: path,
+            data: Mutex::new(JsonData {
+                entries: entries,
+                dirty: false,
+            }),
+        })
+    }
+}
+
+/// Parse the file contents of a JsonFileBrain
+fn decode(raw: &str) -> Result<BTreeMap<String, String>, BrainError> {
+    let obj = match try!(Json::from_str(raw)) {
+        Json::Object(obj) => obj,
+        _ => return Err(BrainError::Format("root is not an object".to_owned()))
+    };
+
+    let mut entries = BTreeMap::new();
+    for (key, value) in obj {
+        match value {
+            Json::String(value) => { entries.insert(key, value); },
+            _ => return Err(BrainError::Format(format!("value of `{}` is not a string", key)))
+        }
+    }
+
+    Ok(entries)
+}
+
+impl Brain for JsonFileBrain {
+    fn get(&self, key: &str) -> Option<String> {
+        self.data.lock().unwrap().entries.get(key).cloned()
+    }
+
+    fn set(&self, key: &str, value: String) {
+        let mut data = self.data.lock().unwrap();
+        data.entries.insert(key.to_owned(), value);
+        data.dirty = true;
+    }
+
+    fn remove(&self, key: &str) -> Option<String> {
+        let mut data = self.data.lock().unwrap();
+        let removed = data.entries.remove(key);
+        data.dirty = data.dirty || removed.is_some();
+        removed
+    }
+
+    fn keys(&self) -> Vec<String> {
+        self.data.lock().unwrap().entries.keys().cloned().collect()
+    }
+
+    fn flush(&self) -> Result<(), BrainError> {
+        let mut data = self.data.lock().unwrap();
+        if !data.dirty {
+            return Ok(());
+        }
+
+        let obj = data.entries.iter()
+                              .map(|(k, v)| (k.clone(), Json::String(v.clone())))
+                              .collect::<BTreeMap<_, _>>();
+        let encoded = format!("{}", json::as_pretty_json(&Json::Object(obj)));
+
+        let mut tmp = self.path.clone().into_os_string();
+        tmp.push(".tmp");
+        {
+            let mut file = try!(File::create(&tmp));
+            try!(file.write_all(encoded.as_bytes()));
+            try!(file.sync_all());
+        }
+        try!(fs::rename(&tmp, &self.path));
+
+        data.dirty = false;
+        Ok(())
+    }
+}
+
+/// A view of a Brain which keeps one handler's or adapter's keys apart from everyone else's
+///
+/// Keys are stored in the underlying brain as `handler/<name>:<key>` or `adapter/<name>:<key>`.
+/// Any `%` or `:` in the name is percent-encoded, so one name's keys can't overlap another's.
+#[derive(Clone)]
+pub struct Namespace {
+    brain: Arc<Brain>,
+    prefix: String,
+}
+
+impl Namespace {
+    /// The namespace for the handler called `name`
+    pub fn handler(brain: Arc<Brain>, name: &str) -> Namespace {
+        Namespace::new(brain, "handler", name)
+    }
+
+    /// The namespace for the adapter called `name`
+    pub fn adapter(brain: Arc<Brain>, name: &str) -> Namespace {
+        Namespace::new(brain, "adapter", name)
+    }
+
+    fn new(brain: Arc<Brain>, kind: &str, name: &str) -> Namespace {
+        let name = name.replace('%', "%25").replace(':', "%3A");
+
+        Namespace {
+            brain: brain,
+            prefix: format!("{}/{}:", kind, name),
+        }
+    }
+
+    fn key(&self, key: &str) -> String {
+        format!("{}{}", self.prefix, key)
+    }
+
+    /// Get the value stored under `key`
+    pub fn get(&self, key: &str) -> Option<String> {
+        self.brain.get(&self.key(key))
+    }
+
+    /// Store `value` under `key`
+    pub fn set(&self, key: &str, value: String) {
+        self.brain.set(&self.key(key), value)
+    }
+
+    /// Remove `key`, returning its value if it had one
+    pub fn remove(&self, key: &str) -> Option<String> {
+        self.brain.remove(&self.key(key))
+    }
+
+    /// Every key in this namespace, without the namespace prefix
+    pub fn keys(&self) -> Vec<String> {
+        self.brain.keys().into_iter()
+                         .filter(|k| k.starts_with(&self.prefix))
+                         .map(|k| k[self.prefix.len()..].to_owned())
+                         .collect()
+    }
+}
+
+impl fmt::Debug for Namespace {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "Namespace({:?})", self.prefix)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::env;
+    use std::fs;
+    use std::sync::Arc;
+
+    use brain::{Brain, JsonFileBrain, MemoryBrain, Namespace};
+
+    #[test]
+    fn test_memory_brain() {
+        let brain = MemoryBrain::new();
+        assert_eq!(brain.get("karma"), None);
+        brain.set("karma", "3".to_owned());
+        assert_eq!(brain.get("karma"), Some("3".to_owned()));
+        assert_eq!(brain.remove("karma"), Some("3".to_owned()));
+        assert!(brain.keys().is_empty());
+    }
+
+    #[test]
+    fn test_namespaces_are_isolated() {
+        let brain = Arc::new(MemoryBrain::new());
+        let karma = Namespace::handler(brain.clone(), "karma");
+        let seen = Namespace::handler(brain.clone(), "seen");
+
+        karma.set("joe", "5".to_owned());
+        seen.set("joe", "yesterday".to_owned());
+
+        assert_eq!(karma.get("joe"), Some("5".to_owned()));
+        assert_eq!(seen.get("joe"), Some("yesterday".to_owned()));
+        assert_eq!(karma.keys(), vec!["joe".to_owned()]);
+        assert_eq!(brain.get("handler/karma:joe"), Some("5".to_owned()));
+    }
+
+    #[test]
+    fn test_namespace_names_are_escaped() {
+        let brain = Arc::new(MemoryBrain::new());
+        Namespace::adapter(brain.clone(), "irc").set("channels", "#rust".to_owned());
+
+        // A handler can't reach an adapter's keys by its choice of name
+        let sneaky = Namespace::handler(brain.clone(), "adapter/irc");
+        assert_eq!(sneaky.get("channels"), None);
+
+        // Nor can names with separators in them overlap
+        let outer = Namespace::handler(brain.clone(), "a");
+        let inner = Namespace::handler(brain.clone(), "a:b");
+        inner.set("c", "1".to_owned());
+        assert_eq!(outer.get("b:c"), None);
+        assert!(outer.keys().is_empty());
+        assert_eq!(brain.get("handler/a%3Ab:c"), Some("1".to_owned()));
+    }
+
+    #[test]
+    fn test_json_file_brain_round_trip() {
+        let name = format!("chatbot-brain-test-{}.json", ::std::process::id());
+        let path = env::temp_dir().join(name);
+        let _ = fs::remove_file(&path);
+
+        {
+            let brain = JsonFileBrain::open(&path).unwrap();
+            brain.set("karma:joe", "5".to_owned());
+            brain.set("seen:joe", "yesterday".to_owned());
+            brain.flush().unwrap();
+        }
+
+        let brain = JsonFileBrain::open(&path).unwrap();
+        assert_eq!(brain.get("karma:joe"), Some("5".to_owned()));
+        assert_eq!(brain.get("seen:joe"), Some("yesterday".to_owned()));
+
+        fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn test_json_file_brain_rejects_bad_data() {
+        let name = format!("chatbot-brain-bad-{}.json", ::std::process::id());
+        let path = env::temp_dir().join(name);
+        fs::write(&path, r#"{"karma:joe": 5}"#).unwrap();
+
+        assert!(JsonFileBrain::open(&path).is_err());
+
+        fs::remove_file(&path).unwrap();
+    }
+}
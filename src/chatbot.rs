@@ -1,24 +1,575 @@
-use std::sync::mpsc::channel;
+use std::any::Any;
+use std::collections::{HashMap, VecDeque};
+use std::error::Error;
+use std::fmt;
+use std::panic::{self, AssertUnwindSafe};
+use std::sync::{Arc, Mutex};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::mpsc::{channel, Receiver, RecvTimeoutError, SendError, Sender};
+use std::thread::{self, JoinHandle};
+use std::time::{Duration, Instant};
+
+use ctrlc;
 
 use adapter::ChatAdapter;
-use handler::MessageHandler;
-use message::IncomingMessage;
+use brain::{Brain, MemoryBrain, Namespace};
+use event::IncomingEvent;
+use handler::{EventHandler, FailurePolicy, HandlerError, HandlerResult, HelpHandler,
+              MessageHandler};
+use message::{AdapterMsg, IncomingMessage, Target};
+use worker::WorkerPool;
 
-fn dispatch(handlers: &Vec<Box<MessageHandler>>, msg: &IncomingMessage) {
-    let msg_str = msg.get_contents();
+/// The receiving ends of the channels adapters send to
+struct Incoming {
+    messages: Receiver<IncomingMessage>,
+    /// Only present when the bot has event handlers
+    events: Option<Receiver<IncomingEvent>>,
+}
+
+/// Settings used by handler jobs running on the worker pool
+struct DispatchConfig {
+    brain: Arc<Brain>,
+    policy: FailurePolicy,
+    timeout: Duration,
+    /// The adapter and channel told when a handler is disabled
+    operator: Option<(String, String)>,
+    handle: BotHandle,
+}
+
+/// State of a registered handler shared between the main loop and the workers
+struct HandlerState {
+    /// Messages or events waiting for the handler
+    queue: VecDeque<Work>,
+    /// Whether a job is currently draining the queue
+    busy: bool,
+    /// When the current call started and the work it is handling
+    current: Option<(Instant, Work)>,
+    /// Whether the current call has already been reported as timed out
+    timed_out: bool,
+    failures: VecDeque<Instant>,
+    disabled: bool,
+}
+
+impl HandlerState {
+    fn new() -> HandlerState {
+        HandlerState {
+            queue: VecDeque::new(),
+            busy: false,
+            current: None,
+            timed_out: false,
+            failures: VecDeque::new(),
+            disabled: false,
+        }
+    }
+
+    /// Record a failure at `now`. Returns true if the handler is disabled as a result.
+    fn record_failure(&mut self, policy: &FailurePolicy, now: Instant) -> bool {
+        if policy.max_failures == 0 {
+            return false;
+        }
+
+        self.failures.push_back(now);
+        while let Some(&oldest) = self.failures.front() {
+            if now.duration_since(oldest) > policy.window {
+                self.failures.pop_front();
+            } else {
+                break;
+            }
+        }
+
+        if self.failures.len() >= policy.max_failures {
+            self.disabled = true;
+            self.queue.clear();
+        }
+
+        self.disabled
+    }
+}
+
+/// A registered handler of either kind
+#[derive(Clone)]
+enum Handler {
+    Message(Arc<MessageHandler>),
+    Event(Arc<EventHandler>),
+}
+
+impl Handler {
+    fn name(&self) -> &str {
+        match *self {
+            Handler::Message(ref handler) => handler.name(),
+            Handler::Event(ref handler) => handler.name(),
+        }
+    }
+
+    fn timeout(&self) -> Option<Duration> {
+        match *self {
+            Handler::Message(ref handler) => handler.timeout(),
+            Handler::Event(ref handler) => handler.timeout(),
+        }
+    }
+}
 
-    for handler in handlers {
-        if handler.can_handle(msg_str) {
-            match handler.handle(msg) {
-                Err(e) => {
-                    println!("Error in handler `{}`", handler.name());
-                    println!("{:?}", e);
-                    println!("The incoming message was {}", msg_str);
+/// Something queued for a handler
+#[derive(Clone)]
+enum Work {
+    Message(IncomingMessage),
+    Event(IncomingEvent),
+}
+
+impl Work {
+    /// A copy of the work carrying `brain` for the handler about to receive it
+    fn with_brain(&self, brain: Namespace) -> Work {
+        match *self {
+            Work::Message(ref msg) => Work::Message(msg.with_brain(brain)),
+            Work::Event(ref event) => Work::Event(event.with_brain(brain)),
+        }
+    }
+}
 
-                    // TODO remove handler?
+impl fmt::Debug for Work {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            Work::Message(ref msg) => msg.fmt(f),
+            Work::Event(ref event) => event.fmt(f),
+        }
+    }
+}
+
+/// A registered handler
+#[derive(Clone)]
+struct HandlerEntry {
+    handler: Handler,
+    state: Arc<Mutex<HandlerState>>,
+}
+
+impl HandlerEntry {
+    fn new(handler: Arc<MessageHandler>) -> HandlerEntry {
+        HandlerEntry::with_handler(Handler::Message(handler))
+    }
+
+    fn event(handler: Arc<EventHandler>) -> HandlerEntry {
+        HandlerEntry::with_handler(Handler::Event(handler))
+    }
+
+    fn with_handler(handler: Handler) -> HandlerEntry {
+        HandlerEntry {
+            handler: handler,
+            state: Arc::new(Mutex::new(HandlerState::new())),
+        }
+    }
+}
+
+/// Get a printable message out of a panic payload
+fn panic_message(payload: Box<Any + Send>) -> String {
+    if let Some(s) = payload.downcast_ref::<&str>() {
+        return (*s).to_owned();
+    }
+
+    match payload.downcast::<String>() {
+        Ok(s) => *s,
+        Err(_) => "unknown panic".to_owned()
+    }
+}
+
+/// Run a handler, turning a panic into a `HandlerError::Panic`
+fn call_handler(handler: &Handler, work: &Work) -> HandlerResult {
+    let res = panic::catch_unwind(AssertUnwindSafe(|| match (handler, work) {
+        (&Handler::Message(ref handler), &Work::Message(ref msg)) => handler.handle(msg),
+        (&Handler::Event(ref handler), &Work::Event(ref event)) => handler.handle(event),
+        _ => unreachable!("work queued for the wrong kind of handler"),
+    }));
+
+    match res {
+        Ok(res) => res,
+        Err(payload) => Err(HandlerError::Panic(panic_message(payload)))
+    }
+}
+
+/// Log a handler failure and disable the handler if the policy says so
+fn report_failure(handler: &Handler, state: &mut HandlerState, work: &Work, err: HandlerError,
+                  config: &DispatchConfig) {
+    println!("Error in handler `{}`", handler.name());
+    println!("{:?}", err);
+    println!("The handler was given {:?}", work);
+
+    if state.record_failure(&config.policy, Instant::now()) {
+        let notice = format!("Handler `{}` disabled after {} failures; last error: {}",
+                             handler.name(), state.failures.len(), err);
+        println!("{}", notice);
+
+        if let Some((ref adapter, ref channel)) = config.operator {
+            let target = Target::Channel(channel.to_owned());
+            if let Err(e) = config.handle.send(adapter, target, &notice) {
+                println!("Unable to notify operator channel: {}", e);
+            }
+        }
+    }
+}
+
+/// Handle queued work for one handler until its queue is empty
+fn drain(entry: HandlerEntry, config: Arc<DispatchConfig>) {
+    loop {
+        let work = {
+            let mut state = entry.state.lock().unwrap();
+            match state.queue.pop_front() {
+                Some(work) => {
+                    state.current = Some((Instant::now(), work.clone()));
+                    state.timed_out = false;
+                    work
                 },
-                _ => ()
+                None => {
+                    state.busy = false;
+                    return;
+                }
             }
+        };
+
+        let brain = Namespace::handler(config.brain.clone(), entry.handler.name());
+        let res = call_handler(&entry.handler, &work.with_brain(brain));
+
+        let mut state = entry.state.lock().unwrap();
+        state.current = None;
+        match res {
+            // The timeout already counted against the handler, so the late result doesn't
+            Err(e) if state.timed_out => {
+                println!("Handler `{}` failed after timing out: {}", entry.handler.name(), e);
+            },
+            Err(e) => report_failure(&entry.handler, &mut state, &work, e, &config),
+            Ok(()) => (),
+        }
+    }
+}
+
+/// Queue work for a handler, starting a job if the handler is idle
+fn enqueue(entry: &HandlerEntry, work: Work, pool: &WorkerPool, config: &Arc<DispatchConfig>) {
+    let mut state = entry.state.lock().unwrap();
+    if state.disabled {
+        return;
+    }
+
+    state.queue.push_back(work);
+    if !state.busy {
+        state.busy = true;
+        let entry = entry.clone();
+        let config = config.clone();
+        pool.execute(move || drain(entry, config));
+    }
+}
+
+/// Queue a message for every interested message handler
+fn dispatch(handlers: &[HandlerEntry], msg: &IncomingMessage, pool: &WorkerPool,
+            config: &Arc<DispatchConfig>) {
+    let msg_str = msg.get_contents();
+
+    for entry in handlers {
+        if let Handler::Message(ref handler) = entry.handler {
+            if handler.can_handle(msg_str) {
+                enqueue(entry, Work::Message(msg.clone()), pool, config);
+            }
+        }
+    }
+}
+
+/// Queue an event for every event handler subscribed to its kind
+fn dispatch_event(handlers: &[HandlerEntry], event: &IncomingEvent, pool: &WorkerPool,
+                  config: &Arc<DispatchConfig>) {
+    for entry in handlers {
+        if let Handler::Event(ref handler) = entry.handler {
+            if handler.can_handle(event.kind()) {
+                enqueue(entry, Work::Event(event.clone()), pool, config);
+            }
+        }
+    }
+}
+
+/// Deliver events from the adapters to the event handlers until `stop` is set or every adapter
+/// has dropped its event sender
+fn route_events(handlers: Vec<HandlerEntry>, rx: Receiver<IncomingEvent>, pool: WorkerPool,
+                config: Arc<DispatchConfig>, stop: Arc<AtomicBool>) {
+    while !stop.load(Ordering::SeqCst) {
+        match rx.recv_timeout(Duration::from_millis(SHUTDOWN_POLL_MS)) {
+            Ok(event) => dispatch_event(&handlers, &event, &pool, &config),
+            Err(RecvTimeoutError::Timeout) => continue,
+            Err(RecvTimeoutError::Disconnected) => break
+        }
+    }
+}
+
+/// Report handlers whose current call has run past its timeout. The call can't be interrupted, so
+/// it keeps its worker thread until it returns, and whatever it returns is ignored.
+fn check_timeouts(handlers: &[HandlerEntry], config: &DispatchConfig) {
+    let now = Instant::now();
+
+    for entry in handlers {
+        let mut state = entry.state.lock().unwrap();
+        let limit = entry.handler.timeout().unwrap_or(config.timeout);
+
+        let overdue = match state.current {
+            Some((started, ref msg)) if !state.timed_out && now.duration_since(started) > limit => {
+                Some((now.duration_since(started), msg.clone()))
+            },
+            _ => None
+        };
+
+        if let Some((elapsed, msg)) = overdue {
+            state.timed_out = true;
+            report_failure(&entry.handler, &mut state, &msg, HandlerError::Timeout(elapsed),
+                           config);
+        }
+    }
+}
+
+/// Wait up to `grace` for every handler to finish its queued messages
+fn wait_idle(handlers: &[HandlerEntry], grace: Duration) {
+    let start = Instant::now();
+
+    while handlers.iter().any(|entry| entry.state.lock().unwrap().busy) {
+        if start.elapsed() > grace {
+            println!("Chatbot: handlers still running after {}s; not waiting",
+                     grace.as_secs());
+            return;
+        }
+
+        thread::sleep(Duration::from_millis(10));
+    }
+}
+
+/// Flush the brain, logging any failure
+fn flush_brain(brain: &Brain) {
+    if let Err(e) = brain.flush() {
+        println!("Chatbot: failed to flush brain: {}", e);
+    }
+}
+
+/// How often the brain is flushed while the bot is running
+const BRAIN_FLUSH_SECS: u64 = 30;
+
+/// Default for how long a handler may run before it is reported as timed out
+const DEFAULT_HANDLER_TIMEOUT_SECS: u64 = 30;
+
+/// Default number of threads handlers run on
+const DEFAULT_WORKER_THREADS: usize = 4;
+
+/// How long shutdown waits for running handlers to send their replies
+const SHUTDOWN_GRACE_SECS: u64 = 5;
+
+/// How long the main loop waits for a message before checking for a shutdown request and for
+/// handlers which have timed out.
+const SHUTDOWN_POLL_MS: u64 = 100;
+
+/// A cloneable handle used to stop a running Chatbot from another thread.
+///
+/// Calling [`shutdown`](#method.shutdown) causes [`Chatbot::run`](struct.Chatbot.html#method.run)
+/// to stop routing messages, send `AdapterMsg::Shutdown` to every adapter, wait for the adapters to
+/// finish, and return.
+#[derive(Clone, Debug)]
+pub struct ShutdownHandle {
+    requested: Arc<AtomicBool>,
+}
+
+impl ShutdownHandle {
+    fn new() -> ShutdownHandle {
+        ShutdownHandle {
+            requested: Arc::new(AtomicBool::new(false))
+        }
+    }
+
+    /// Ask the bot to shut down
+    pub fn shutdown(&self) {
+        self.requested.store(true, Ordering::SeqCst);
+    }
+
+    /// Whether a shutdown has been requested
+    pub fn is_shutdown(&self) -> bool {
+        self.requested.load(Ordering::SeqCst)
+    }
+}
+
+/// Failure modes for `BotHandle::send`
+#[derive(Debug)]
+pub enum BotSendError {
+    /// No running adapter accepts proactive messages under this name
+    UnknownAdapter(String),
+    /// The adapter stopped receiving messages
+    Disconnected(SendError<AdapterMsg>),
+}
+
+impl Error for BotSendError {
+    fn description(&self) -> &str {
+        match *self {
+            BotSendError::UnknownAdapter(_) => "No running adapter with that name",
+            BotSendError::Disconnected(_) => "Failed to send because adapter disconnected",
+        }
+    }
+
+    fn cause(&self) -> Option<&Error> {
+        match *self {
+            BotSendError::Disconnected(ref err) => Some(err),
+            _ => None
+        }
+    }
+}
+
+impl fmt::Display for BotSendError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            BotSendError::UnknownAdapter(ref name) => write!(f, "Unknown adapter: {}", name),
+            BotSendError::Disconnected(ref err) => write!(f, "Disconnected: {}", err),
+        }
+    }
+}
+
+impl From<SendError<AdapterMsg>> for BotSendError {
+    fn from(err: SendError<AdapterMsg>) -> BotSendError {
+        BotSendError::Disconnected(err)
+    }
+}
+
+/// A cloneable handle for sending messages without an IncomingMessage to reply to
+///
+/// Use it for scheduled announcements, notifications triggered from outside the chat, or
+/// greetings. Messages can only be delivered while the bot is running.
+///
+/// # Examples
+///
+/// ```no_run
+/// # #[macro_use(handler)]
+/// # extern crate chatbot;
+/// # fn main() {
+/// use std::thread;
+///
+/// use chatbot::Chatbot;
+/// use chatbot::adapter::CliAdapter;
+/// use chatbot::message::Target;
+///
+/// let mut bot = Chatbot::new("announcer");
+/// bot.add_adapter(CliAdapter::new("announcer"));
+/// bot.add_handler(handler!("Ping", r"ping", |_, _| Some("pong".to_owned())));
+///
+/// let handle = bot.handle();
+/// thread::spawn(move || {
+///     handle.send("cli", Target::Channel("#general".to_owned()), "Hello!").unwrap();
+/// });
+///
+/// bot.run().unwrap();
+/// # }
+/// ```
+#[derive(Clone)]
+pub struct BotHandle {
+    adapters: Arc<Mutex<HashMap<String, Sender<AdapterMsg>>>>,
+}
+
+impl BotHandle {
+    fn new() -> BotHandle {
+        BotHandle {
+            adapters: Arc::new(Mutex::new(HashMap::new()))
+        }
+    }
+
+    /// Send `text` to `target` through the adapter named `adapter`
+    pub fn send(&self, adapter: &str, target: Target, text: &str) -> Result<(), BotSendError> {
+        let adapters = self.adapters.lock().unwrap();
+        let tx = match adapters.get(adapter) {
+            Some(tx) => tx,
+            None => return Err(BotSendError::UnknownAdapter(adapter.to_owned()))
+        };
+
+        Ok(try!(tx.send(AdapterMsg::Proactive(target, text.to_owned()))))
+    }
+
+    /// Names of the adapters which can currently be sent messages
+    pub fn adapters(&self) -> Vec<String> {
+        self.adapters.lock().unwrap().keys().cloned().collect()
+    }
+}
+
+/// Reasons a Chatbot can't be started
+#[derive(Debug, PartialEq, Eq)]
+pub enum RunError {
+    /// No adapters were added, so there is nothing to listen to
+    NoAdapters,
+    /// No handlers were added, so there is nothing to do with messages
+    NoHandlers,
+}
+
+impl Error for RunError {
+    fn description(&self) -> &str {
+        match *self {
+            RunError::NoAdapters => "Chatbot has no adapters",
+            RunError::NoHandlers => "Chatbot has no handlers",
+        }
+    }
+}
+
+impl fmt::Display for RunError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.description())
+    }
+}
+
+/// A Chatbot running on its own thread
+///
+/// Returned by [`Chatbot::start`](struct.Chatbot.html#method.start).
+///
+/// Dropping a RunningBot does not stop the bot; call [`stop`](#method.stop) for that.
+pub struct RunningBot {
+    name: String,
+    handle: BotHandle,
+    shutdown: ShutdownHandle,
+    running: Arc<AtomicBool>,
+    thread: Option<JoinHandle<()>>,
+}
+
+/// Marks the bot as finished when dropped, so it is cleared even if routing panics
+struct Running(Arc<AtomicBool>);
+
+impl Drop for Running {
+    fn drop(&mut self) {
+        self.0.store(false, Ordering::SeqCst);
+    }
+}
+
+impl RunningBot {
+    /// The bot's name
+    pub fn get_name(&self) -> &str {
+        self.name.as_ref()
+    }
+
+    /// Whether the bot is still routing messages or shutting down. False once it has finished.
+    pub fn is_running(&self) -> bool {
+        self.running.load(Ordering::SeqCst)
+    }
+
+    /// Whether a shutdown has been requested
+    pub fn is_stopping(&self) -> bool {
+        self.shutdown.is_shutdown()
+    }
+
+    /// Names of the adapters which can currently be sent messages
+    pub fn adapters(&self) -> Vec<String> {
+        self.handle.adapters()
+    }
+
+    /// Get a handle for sending messages proactively
+    pub fn handle(&self) -> BotHandle {
+        self.handle.clone()
+    }
+
+    /// Get a handle which can stop the bot from elsewhere
+    pub fn shutdown_handle(&self) -> ShutdownHandle {
+        self.shutdown.clone()
+    }
+
+    /// Ask the bot to shut down. Returns immediately; use `join` to wait for it to finish.
+    pub fn stop(&self) {
+        self.shutdown.shutdown();
+    }
+
+    /// Wait for the bot to finish. Returns an error if the routing thread panicked.
+    pub fn join(mut self) -> thread::Result<()> {
+        match self.thread.take() {
+            Some(thread) => thread.join(),
+            None => Ok(())
         }
     }
 }
@@ -26,12 +577,23 @@ fn dispatch(handlers: &Vec<Box<MessageHandler>>, msg: &IncomingMessage) {
 /// The Chatbot is the central data structure of the chatbot platform. It contains a `run` method
 /// which listens for messages from adapters and routes them to handlers. Any program which uses
 /// chatbot will need to minimally create a Chatbot, add an adapter, add a handler, and call Chatbot
-/// [`run`](chatbot/struct.Chatbot.html#method.run).
+/// [`run`](chatbot/struct.Chatbot.html#method.run), or [`start`](#method.start) to run it in the
+/// background.
 pub struct Chatbot {
     name: String,
-    adapters: Vec<Box<ChatAdapter>>,
-    handlers: Vec<Box<MessageHandler>>,
-    addressed_handlers: Vec<Box<MessageHandler>>,
+    adapters: HashMap<String, Box<ChatAdapter>>,
+    handlers: Vec<HandlerEntry>,
+    addressed_handlers: Vec<HandlerEntry>,
+    event_handlers: Vec<HandlerEntry>,
+    failure_policy: FailurePolicy,
+    operator: Option<(String, String)>,
+    handler_timeout: Duration,
+    worker_threads: usize,
+    help: bool,
+    brain: Arc<Brain>,
+    handle: BotHandle,
+    shutdown: ShutdownHandle,
+    catch_signals: bool,
 }
 
 impl Chatbot {
@@ -43,9 +605,19 @@ impl Chatbot {
     pub fn new(name: &str) -> Chatbot {
         Chatbot {
             name: name.to_owned(),
-            adapters: Vec::new(),
+            adapters: HashMap::new(),
             handlers: Vec::new(),
             addressed_handlers: Vec::new(),
+            event_handlers: Vec::new(),
+            failure_policy: FailurePolicy::default(),
+            operator: None,
+            handler_timeout: Duration::from_secs(DEFAULT_HANDLER_TIMEOUT_SECS),
+            worker_threads: DEFAULT_WORKER_THREADS,
+            help: false,
+            brain: Arc::new(MemoryBrain::new()),
+            handle: BotHandle::new(),
+            shutdown: ShutdownHandle::new(),
+            catch_signals: false,
         }
     }
 
@@ -59,11 +631,18 @@ impl Chatbot {
     /// Add as many adapters as you like. The IncomingMessages sent by adapters are made available
     /// to all handlers regardless of how many adapters exist. The IncomingMessage.reply method
     /// makes sure the response is sent back to the adapter from whence the message came.
+    ///
+    /// Adapters are keyed by `get_name`, which must match the `from_adapter` of the messages they
+    /// produce. Adding a second adapter with the same name replaces the first.
     pub fn add_adapter<T>(&mut self, adapter: T)
         where T: ChatAdapter + 'static
     {
-        println!("Adding adapter {}", adapter.get_name());
-        self.adapters.push(Box::new(adapter))
+        let name = adapter.get_name().to_owned();
+        println!("Adding adapter {}", name);
+
+        if self.adapters.insert(name.clone(), Box::new(adapter)).is_some() {
+            println!("Chatbot: replaced existing adapter {}", name);
+        }
     }
 
     /// Add a MessageHandler to the bot
@@ -75,70 +654,337 @@ impl Chatbot {
         where T: MessageHandler + 'static
     {
         println!("Adding handler {}", handler.name());
-        self.handlers.push(Box::new(handler))
+        self.handlers.push(HandlerEntry::new(Arc::new(handler)))
     }
 
     /// Add a MessageHandler, that requires the bot to be addressed, to the bot
+    ///
+    /// Whether the bot was addressed is decided by the `addresser` of the adapter the message came
+    /// from. The addressing text is removed before the handler sees the message, so a handler for
+    /// `ping` matches `botname: ping`.
     pub fn add_addressed_handler<T>(&mut self, handler: T)
         where T: MessageHandler + 'static
     {
         println!("Adding handler {}", handler.name());
-        self.addressed_handlers.push(Box::new(handler))
+        self.addressed_handlers.push(HandlerEntry::new(Arc::new(handler)))
+    }
+
+    /// Add an EventHandler to the bot
+    ///
+    /// Event handlers receive the membership and topic changes reported by adapters, filtered by
+    /// the handler's `kinds`. Adapters are only asked for events when at least one event handler
+    /// has been added.
+    pub fn add_event_handler<T>(&mut self, handler: T)
+        where T: EventHandler + 'static
+    {
+        println!("Adding event handler {}", handler.name());
+        self.event_handlers.push(HandlerEntry::event(Arc::new(handler)))
+    }
+
+    /// Replace the default in-memory brain
+    ///
+    /// Handlers reach the brain through `IncomingMessage::brain`, each in a namespace named after
+    /// the handler. The brain is flushed every 30 seconds while the bot runs and again when it
+    /// shuts down.
+    pub fn set_brain<B>(&mut self, brain: B)
+        where B: Brain + 'static
+    {
+        self.brain = Arc::new(brain);
+    }
+
+    /// The bot's brain, for code outside of handlers
+    pub fn brain(&self) -> Arc<Brain> {
+        self.brain.clone()
+    }
+
+    /// Answer `help` with a list of the registered handlers
+    ///
+    /// The [`HelpHandler`](handler/struct.HelpHandler.html) is added as an addressed handler when
+    /// the bot starts, so it describes every handler added before `run` is called.
+    pub fn enable_help(&mut self) {
+        self.help = true;
+    }
+
+    /// Set the policy used to disable handlers which keep failing
+    ///
+    /// Handler panics are always caught and logged. By default a handler is disabled after five
+    /// failures in a minute; use `FailurePolicy::never()` to keep every handler running.
+    pub fn set_failure_policy(&mut self, policy: FailurePolicy) {
+        self.failure_policy = policy;
+    }
+
+    /// Set how long a handler may run before it is reported as timed out
+    ///
+    /// Handlers can override this with `MessageHandler::timeout`. A handler which times out keeps
+    /// running in the background; the timeout counts as a failure under the `FailurePolicy`, and
+    /// an error it returns later doesn't count again. It holds on to its worker thread until it
+    /// returns, so handlers which often hang can leave other handlers waiting for a thread; give
+    /// them a low `max_failures` so they are disabled, or add worker threads.
+    pub fn set_handler_timeout(&mut self, timeout: Duration) {
+        self.handler_timeout = timeout;
     }
 
-    /// Start processing messages
+    /// Set the number of threads handlers run on. Defaults to 4.
+    pub fn set_worker_threads(&mut self, threads: usize) {
+        self.worker_threads = threads;
+    }
+
+    /// Name a channel which is told when a handler is disabled
+    ///
+    /// The notice is sent proactively through the adapter named `adapter`, so `channel` is named
+    /// the way that adapter expects, such as `#ops` on IRC or a channel ID on Slack. Adapters
+    /// which can't be sent messages proactively can't be told.
+    pub fn set_operator_channel(&mut self, adapter: &str, channel: &str) {
+        self.operator = Some((adapter.to_owned(), channel.to_owned()));
+    }
+
+    /// Get a handle for sending messages proactively while the bot is running
+    pub fn handle(&self) -> BotHandle {
+        self.handle.clone()
+    }
+
+    /// Get a handle which can be used to stop the bot once it is running
+    pub fn shutdown_handle(&self) -> ShutdownHandle {
+        self.shutdown.clone()
+    }
+
+    /// Shut the bot down cleanly on SIGINT and SIGTERM
+    ///
+    /// The signal handler is installed when `run` is called. Only one handler may be installed per
+    /// process, so this should be enabled on at most one Chatbot.
+    pub fn catch_signals(&mut self) {
+        self.catch_signals = true;
+    }
+
+    /// Start processing messages on the current thread
     ///
     /// Call process_events on all of the adapters and `recv` on the `IncomingMessage` channel.
-    /// Distribute IncomingMessages to list of handlers.
-    pub fn run(&mut self) {
-        let adapters_len = self.adapters.len();
-        let handlers_len = self.handlers.len() + self.addressed_handlers.len();
+    /// Distribute IncomingMessages to list of handlers. Returns once every adapter has stopped
+    /// sending messages or a shutdown is requested through a `ShutdownHandle`. In the latter case
+    /// all adapters are told to disconnect and are waited on before returning.
+    ///
+    /// Fails without starting anything if the bot has no adapters or no handlers. Use
+    /// [`start`](#method.start) to run the bot on a thread of its own instead.
+    pub fn run(&mut self) -> Result<(), RunError> {
+        let incoming = try!(self.prepare());
+        self.route(incoming);
+        Ok(())
+    }
 
-        assert!(adapters_len > 0);
-        assert!(handlers_len > 0);
+    /// Start processing messages on a new thread
+    ///
+    /// The returned `RunningBot` is used to check on the bot, stop it, and wait for it to finish.
+    /// Fails without starting anything if the bot has no adapters or no handlers.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # #[macro_use(handler)]
+    /// # extern crate chatbot;
+    /// # fn main() {
+    /// use chatbot::Chatbot;
+    /// use chatbot::testing::MockAdapter;
+    ///
+    /// let mut bot = Chatbot::new("embedded");
+    /// bot.add_adapter(MockAdapter::new("embedded"));
+    /// bot.add_handler(handler!("Ping", r"ping", |_, _| Some("pong".to_owned())));
+    ///
+    /// let running = bot.start().unwrap();
+    /// assert!(running.is_running());
+    ///
+    /// running.stop();
+    /// running.join().unwrap();
+    /// # }
+    /// ```
+    pub fn start(mut self) -> Result<RunningBot, RunError> {
+        let incoming = try!(self.prepare());
+
+        let running = Arc::new(AtomicBool::new(true));
+        let status = RunningBot {
+            name: self.name.clone(),
+            handle: self.handle(),
+            shutdown: self.shutdown_handle(),
+            running: running.clone(),
+            thread: None,
+        };
 
-        println!("Chatbot: {} adapters", adapters_len);
+        let thread = thread::Builder::new().name(format!("Chatbot {}", self.name)).spawn(move || {
+            let _running = Running(running);
+            self.route(incoming);
+        }).ok().expect("failed to create chatbot thread");
+
+        Ok(RunningBot { thread: Some(thread), ..status })
+    }
+
+    /// Make sure there is something to run, add the built in handlers, and start the adapters
+    fn prepare(&mut self) -> Result<Incoming, RunError> {
+        if self.adapters.is_empty() {
+            return Err(RunError::NoAdapters);
+        }
+
+        let handlers_len = self.handlers.len() + self.addressed_handlers.len() +
+                           self.event_handlers.len();
+
+        if handlers_len == 0 {
+            return Err(RunError::NoHandlers);
+        }
+
+        if self.help {
+            let mut help = HelpHandler::new();
+            let handlers = self.handlers.iter().map(|entry| (entry, false))
+                .chain(self.addressed_handlers.iter().map(|entry| (entry, true)));
+
+            for (entry, addressed) in handlers {
+                if let Handler::Message(ref handler) = entry.handler {
+                    help.add(&**handler, addressed);
+                }
+            }
+
+            self.help = false;
+            self.add_addressed_handler(help);
+        }
+
+        println!("Chatbot: {} adapters", self.adapters.len());
         println!("Chatbot: {} handlers", handlers_len);
 
+        if self.catch_signals {
+            let handle = self.shutdown_handle();
+            if let Err(e) = ctrlc::set_handler(move || handle.shutdown()) {
+                println!("Chatbot: failed to install signal handler: {}", e);
+            }
+        }
+
         let (incoming_tx, incoming_rx) = channel();
+        let (events_tx, events_rx) = channel();
+        let wants_events = !self.event_handlers.is_empty();
 
-        for adapter in &mut self.adapters {
+        for (name, adapter) in self.adapters.iter_mut() {
+            adapter.set_brain(Namespace::adapter(self.brain.clone(), name));
+            if wants_events {
+                adapter.set_event_sender(events_tx.clone());
+            }
             adapter.process_events(incoming_tx.clone());
+
+            if let Some(tx) = adapter.outgoing() {
+                self.handle.adapters.lock().unwrap().insert(name.clone(), tx);
+            }
         }
 
+        // The adapters hold the only senders now
+        drop(incoming_tx);
+        drop(events_tx);
+
+        Ok(Incoming {
+            messages: incoming_rx,
+            events: if wants_events { Some(events_rx) } else { None },
+        })
+    }
+
+    /// Route messages from the adapters to the handlers until shut down
+    fn route(&mut self, incoming: Incoming) {
+        let config = Arc::new(DispatchConfig {
+            brain: self.brain.clone(),
+            policy: self.failure_policy,
+            timeout: self.handler_timeout,
+            operator: self.operator.clone(),
+            handle: self.handle.clone(),
+        });
+        let pool = WorkerPool::new(self.worker_threads);
+        let incoming_rx = incoming.messages;
+
+        // Events have a thread of their own so neither stream waits on the other
+        let stop_events = Arc::new(AtomicBool::new(false));
+        let events_thread = incoming.events.map(|events_rx| {
+            let handlers = self.event_handlers.clone();
+            let pool = pool.clone();
+            let config = config.clone();
+            let stop = stop_events.clone();
+
+            thread::Builder::new().name("Chatbot Events".to_owned()).spawn(move || {
+                route_events(handlers, events_rx, pool, config, stop);
+            }).ok().expect("failed to create event thread")
+        });
+
+        let mut last_flush = Instant::now();
+
         loop {
+            if self.shutdown.is_shutdown() {
+                break;
+            }
+
+            if last_flush.elapsed() > Duration::from_secs(BRAIN_FLUSH_SECS) {
+                flush_brain(&*self.brain);
+                last_flush = Instant::now();
+            }
+
+            check_timeouts(&self.handlers, &config);
+            check_timeouts(&self.addressed_handlers, &config);
+            check_timeouts(&self.event_handlers, &config);
+
             // Get message from adapter
-            let msg = match incoming_rx.recv() {
+            let msg = match incoming_rx.recv_timeout(Duration::from_millis(SHUTDOWN_POLL_MS)) {
                 Ok(msg) => msg,
-                Err(_) => break
+                Err(RecvTimeoutError::Timeout) => continue,
+                Err(RecvTimeoutError::Disconnected) => break
             };
 
-            let mut addressed = false;
-
-            // TODO this should only check the source adapter
-            for adapter in &self.adapters {
-                if adapter.addresser().is_match(msg.get_contents()) {
-                    addressed = true;
+            // Only the adapter which produced the message knows how it addresses the bot
+            let addressed = match self.adapters.get(msg.from_adapter()) {
+                Some(adapter) => adapter.address(&msg),
+                None => {
+                    println!("Chatbot: message from unknown adapter {}", msg.from_adapter());
+                    None
                 }
-            }
+            };
 
             // Only dispatch to addressed handlers when bot is addressed
-            if addressed {
-                dispatch(&self.addressed_handlers, &msg);
+            if let Some(contents) = addressed {
+                dispatch(&self.addressed_handlers, &msg.with_contents(contents), &pool, &config);
             }
 
             // Always dispatch to global handlers
-            dispatch(&self.handlers, &msg);
+            dispatch(&self.handlers, &msg, &pool, &config);
         }
 
         println!("chatbot shutting down");
+
+        self.handle.adapters.lock().unwrap().clear();
+
+        stop_events.store(true, Ordering::SeqCst);
+        if let Some(events_thread) = events_thread {
+            let _ = events_thread.join();
+        }
+
+        let grace = Duration::from_secs(SHUTDOWN_GRACE_SECS);
+        wait_idle(&self.handlers, grace);
+        wait_idle(&self.addressed_handlers, grace);
+        wait_idle(&self.event_handlers, grace);
+        flush_brain(&*self.brain);
+
+        for adapter in self.adapters.values_mut() {
+            println!("Chatbot: stopping adapter {}", adapter.get_name());
+            adapter.shutdown();
+        }
     }
 }
 
 #[cfg(test)]
 mod tests {
-    use chatbot::Chatbot;
+    use std::sync::Arc;
+    use std::sync::mpsc::{channel, Receiver};
+    use std::thread;
+    use std::time::{Duration, Instant};
+
+    use chatbot::{BotHandle, BotSendError, Chatbot, DispatchConfig, HandlerEntry, HandlerState,
+                  RunError, check_timeouts, dispatch, wait_idle};
     use adapter::CliAdapter;
+    use regex::Regex;
+
+    use brain::MemoryBrain;
+    use handler::{FailurePolicy, HandlerResult, MessageHandler};
+    use message::{AdapterMsg, IncomingMessage, Target};
+    use testing::MockAdapter;
+    use worker::WorkerPool;
 
     static NAME: &'static str = "testbot";
 
@@ -163,4 +1009,277 @@ mod tests {
         });
         bot.add_handler(echo);
     }
+
+    #[test]
+    fn test_shutdown_handle() {
+        let bot = Chatbot::new(NAME);
+        let handle = bot.shutdown_handle();
+        assert!(!handle.is_shutdown());
+        handle.clone().shutdown();
+        assert!(handle.is_shutdown());
+    }
+
+    #[test]
+    fn test_run_requires_adapters_and_handlers() {
+        let mut bot = Chatbot::new(NAME);
+        assert_eq!(bot.run(), Err(RunError::NoAdapters));
+
+        bot.add_adapter(MockAdapter::new(NAME));
+        assert_eq!(bot.run(), Err(RunError::NoHandlers));
+
+        // The help handler has nothing to describe on its own
+        bot.enable_help();
+        assert_eq!(bot.run(), Err(RunError::NoHandlers));
+        assert_eq!(bot.start().err(), Some(RunError::NoHandlers));
+    }
+
+    #[test]
+    fn test_start_and_stop() {
+        let mut bot = Chatbot::new(NAME);
+        bot.add_adapter(MockAdapter::new(NAME));
+        bot.add_handler(handler!("Ping", r"ping", |_, _| Some("pong".to_owned())));
+
+        let running = bot.start().unwrap();
+        assert_eq!(running.get_name(), NAME);
+        assert!(running.is_running());
+        assert!(!running.is_stopping());
+        assert_eq!(running.adapters(), vec!["mock".to_owned()]);
+
+        running.stop();
+        assert!(running.is_stopping());
+
+        let start = Instant::now();
+        while running.is_running() {
+            assert!(start.elapsed() < Duration::from_secs(5));
+            thread::sleep(Duration::from_millis(10));
+        }
+        assert!(running.adapters().is_empty());
+        running.join().unwrap();
+    }
+
+    #[test]
+    fn test_handle_send() {
+        let bot = Chatbot::new(NAME);
+        let handle = bot.handle();
+
+        match handle.send("cli", Target::User("joe".to_owned()), "hi") {
+            Err(BotSendError::UnknownAdapter(name)) => assert_eq!(name, "cli"),
+            _ => panic!("expected UnknownAdapter")
+        }
+
+        let (tx, rx) = channel();
+        bot.handle.adapters.lock().unwrap().insert("cli".to_owned(), tx);
+        handle.clone().send("cli", Target::Channel("#chan".to_owned()), "hi").unwrap();
+
+        match rx.recv().unwrap() {
+            AdapterMsg::Proactive(target, text) => {
+                assert_eq!(target, Target::Channel("#chan".to_owned()));
+                assert_eq!(text, "hi");
+            },
+            _ => unreachable!()
+        }
+    }
+
+    /// Settings which tell #ops on the `ops` adapter about disabled handlers, and the receiver
+    /// for that adapter
+    fn config(policy: FailurePolicy, timeout: Duration)
+        -> (Arc<DispatchConfig>, Receiver<AdapterMsg>)
+    {
+        let handle = BotHandle::new();
+        let (tx, rx) = channel();
+        handle.adapters.lock().unwrap().insert("ops".to_owned(), tx);
+
+        let config = Arc::new(DispatchConfig {
+            brain: Arc::new(MemoryBrain::new()),
+            policy: policy,
+            timeout: timeout,
+            operator: Some(("ops".to_owned(), "#ops".to_owned())),
+            handle: handle,
+        });
+        (config, rx)
+    }
+
+    fn recv_notice(rx: &Receiver<AdapterMsg>) -> String {
+        match rx.recv_timeout(Duration::from_secs(5)).unwrap() {
+            AdapterMsg::Proactive(Target::Channel(ref chan), text) if chan == "#ops" => text,
+            _ => unreachable!()
+        }
+    }
+
+    fn recv_text(rx: &Receiver<AdapterMsg>) -> (Option<String>, String) {
+        match rx.recv_timeout(Duration::from_secs(5)).unwrap() {
+            AdapterMsg::Outgoing(out) => {
+                (out.get_incoming().channel().map(|c| c.to_owned()), out.as_ref().to_owned())
+            },
+            _ => unreachable!()
+        }
+    }
+
+    #[test]
+    fn test_failure_window() {
+        let mut state = HandlerState::new();
+        let policy = FailurePolicy::new(2, Duration::from_secs(10));
+        let start = Instant::now();
+
+        assert!(!state.record_failure(&policy, start));
+        // The first failure has aged out of the window
+        assert!(!state.record_failure(&policy, start + Duration::from_secs(11)));
+        assert!(state.record_failure(&policy, start + Duration::from_secs(12)));
+    }
+
+    #[test]
+    fn test_never_disable() {
+        let mut state = HandlerState::new();
+        let now = Instant::now();
+
+        for _ in 0..10 {
+            assert!(!state.record_failure(&FailurePolicy::never(), now));
+        }
+    }
+
+    #[test]
+    fn test_panicking_handler_is_disabled() {
+        let panics = handler!("Panics", r"boom", |_, _| panic!("handler exploded"));
+        let echo = handler!("Echo", r"boom", |_, msg| Some(msg.to_owned()));
+        let handlers = vec![HandlerEntry::new(Arc::new(panics)),
+                            HandlerEntry::new(Arc::new(echo))];
+        let pool = WorkerPool::new(2);
+
+        let (tx, rx) = channel();
+        let msg = IncomingMessage::new("test".to_owned(), None, Some("#chan".to_owned()), None,
+                                       "boom".to_owned(), tx);
+        let (config, ops) = config(FailurePolicy::new(1, Duration::from_secs(60)),
+                                   Duration::from_secs(30));
+        dispatch(&handlers, &msg, &pool, &config);
+        wait_idle(&handlers, Duration::from_secs(5));
+
+        assert!(handlers[0].state.lock().unwrap().disabled);
+        assert!(!handlers[1].state.lock().unwrap().disabled);
+
+        // The notice goes to the operator's adapter rather than back where the message came from
+        assert_eq!(recv_text(&rx), (Some("#chan".to_owned()), "boom".to_owned()));
+        assert!(recv_notice(&ops).contains("Panics"));
+        assert!(rx.try_recv().is_err());
+    }
+
+    /// Counts the messages it sees in the brain
+    struct Counter {
+        regex: Regex,
+    }
+
+    impl MessageHandler for Counter {
+        fn name(&self) -> &str {
+            "Counter"
+        }
+
+        fn re(&self) -> &Regex {
+            &self.regex
+        }
+
+        fn handle(&self, msg: &IncomingMessage) -> HandlerResult {
+            let brain = msg.brain().unwrap();
+            let n = brain.get("n").map(|n| n.parse::<u32>().unwrap()).unwrap_or(0);
+            brain.set("n", (n + 1).to_string());
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn test_handlers_get_namespaced_brain() {
+        let handlers = vec![HandlerEntry::new(Arc::new(Counter { regex: regex!(r"count") }))];
+        let pool = WorkerPool::new(1);
+        let (config, _ops) = config(FailurePolicy::default(), Duration::from_secs(30));
+
+        let (tx, _rx) = channel();
+        for _ in 0..3 {
+            let msg = IncomingMessage::new("test".to_owned(), None, None, None,
+                                           "count".to_owned(), tx.clone());
+            dispatch(&handlers, &msg, &pool, &config);
+        }
+        wait_idle(&handlers, Duration::from_secs(5));
+
+        assert_eq!(config.brain.get("handler/Counter:n"), Some("3".to_owned()));
+    }
+
+    #[test]
+    fn test_replies_stay_in_order() {
+        // Earlier messages take longer to handle than later ones
+        let slow = handler!("Slow", r"\d", |_, msg| {
+            let n: u64 = msg.parse().unwrap();
+            thread::sleep(Duration::from_millis(50 - n * 10));
+            Some(msg.to_owned())
+        });
+        let handlers = vec![HandlerEntry::new(Arc::new(slow))];
+        let pool = WorkerPool::new(4);
+        let (config, _ops) = config(FailurePolicy::default(), Duration::from_secs(30));
+
+        let (tx, rx) = channel();
+        for n in 0..5 {
+            let msg = IncomingMessage::new("test".to_owned(), None, None, None, n.to_string(),
+                                           tx.clone());
+            dispatch(&handlers, &msg, &pool, &config);
+        }
+
+        for n in 0..5 {
+            assert_eq!(recv_text(&rx).1, n.to_string());
+        }
+    }
+
+    #[test]
+    fn test_handler_timeout() {
+        let (release_tx, release_rx) = channel::<()>();
+        let release_rx = ::std::sync::Mutex::new(release_rx);
+        let stuck = handler!("Stuck", r"wait", move |_, _| {
+            release_rx.lock().unwrap().recv().unwrap();
+            None
+        });
+        let handlers = vec![HandlerEntry::new(Arc::new(stuck))];
+        let pool = WorkerPool::new(1);
+        let (config, ops) = config(FailurePolicy::new(1, Duration::from_secs(60)),
+                                   Duration::from_millis(10));
+
+        let (tx, _rx) = channel();
+        let msg = IncomingMessage::new("test".to_owned(), None, None, None, "wait".to_owned(), tx);
+        dispatch(&handlers, &msg, &pool, &config);
+
+        thread::sleep(Duration::from_millis(50));
+        check_timeouts(&handlers, &config);
+
+        assert!(recv_notice(&ops).contains("Timed out"));
+        assert!(handlers[0].state.lock().unwrap().disabled);
+
+        release_tx.send(()).unwrap();
+    }
+
+    #[test]
+    fn test_late_failure_counted_once() {
+        let (release_tx, release_rx) = channel::<()>();
+        let release_rx = ::std::sync::Mutex::new(release_rx);
+        let stuck = handler!("Stuck", r"wait", move |_, _| {
+            release_rx.lock().unwrap().recv().unwrap();
+            panic!("failed after the timeout")
+        });
+        let handlers = vec![HandlerEntry::new(Arc::new(stuck))];
+        let pool = WorkerPool::new(1);
+        let config = Arc::new(DispatchConfig {
+            brain: Arc::new(MemoryBrain::new()),
+            policy: FailurePolicy::new(2, Duration::from_secs(60)),
+            timeout: Duration::from_millis(10),
+            operator: None,
+            handle: BotHandle::new(),
+        });
+
+        let (tx, _rx) = channel();
+        let msg = IncomingMessage::new("test".to_owned(), None, None, None, "wait".to_owned(), tx);
+        dispatch(&handlers, &msg, &pool, &config);
+
+        thread::sleep(Duration::from_millis(50));
+        check_timeouts(&handlers, &config);
+        release_tx.send(()).unwrap();
+        wait_idle(&handlers, Duration::from_secs(5));
+
+        let state = handlers[0].state.lock().unwrap();
+        assert_eq!(state.failures.len(), 1);
+        assert!(!state.disabled);
+    }
 }
@@ -0,0 +1,633 @@
+//! Build a fully configured Chatbot from a TOML file.
+//!
+//! The file names the bot, lists the adapters to connect, and picks which built in handlers to
+//! enable. Handlers written in code can still be added to the Chatbot returned by
+//! [`Config::build`](struct.Config.html#method.build) before calling `run`.
+//!
+//! ```toml
+//! name = "chatbotbot"
+//! handler_timeout = 30        # seconds, optional
+//! worker_threads = 4          # optional
+//!
+//! [failure_policy]            # optional
+//! max_failures = 5
+//! window = 60                 # seconds
+//!
+//! [operator]                  # optional, told when a handler is disabled
+//! adapter = "mozilla"         # the name of the adapter to send through
+//! channel = "#ops"            # as that adapter names it, e.g. a channel ID on slack
+//!
+//! [brain]                     # optional; memories are lost on exit without it
+//! path = "brain.json"
+//!
+//! [handlers.help]
+//! enabled = true
+//!
+//! [[adapters]]
+//! type = "cli"
+//!
+//! [[adapters]]
+//! type = "irc"                # requires the irc-adapter feature
+//! name = "mozilla"            # optional, needed to tell several irc adapters apart
+//! server = "irc.mozilla.org"
+//! port = 6697                 # optional
+//! use_ssl = true              # optional
+//! nickname = "chatbotbot"     # optional, defaults to the bot name
+//! alt_nicks = ["chatbotbot_"] # optional
+//! channels = ["#chatbot"]
+//! nick_password = "hunter2"   # optional, identifies with NickServ before joining channels
+//! sasl_password = "hunter2"   # optional, logs in with SASL PLAIN instead
+//! sasl_account = "chatbotbot" # optional, defaults to the nickname
+//! flood_burst = 5             # optional, lines sent at once before slowing down
+//! flood_rate = 0.5            # optional, lines per second after that
+//! accept_invites = true       # optional, joins channels the bot is invited to
+//! invites_from = ["alice"]    # optional, only accepts invites from these nicks
+//!
+//! [[adapters]]
+//! type = "slack"              # requires the slack-adapter feature
+//! token_env = "SLACK_BOT_TOKEN" # or `token = "xoxb-..."`
+//! handle_edits = true         # optional, runs handlers again on edited messages
+//!
+//! [[adapters]]
+//! type = "slack"
+//! mode = "events"             # optional, receives the Events API over HTTP instead of RTM
+//! token_env = "SLACK_BOT_TOKEN"
+//! signing_secret_env = "SLACK_SIGNING_SECRET" # or `signing_secret = "..."`
+//! listen = "0.0.0.0:3000"     # optional
+//! api_url = "https://slack.com/api/" # optional, e.g. a local stub for testing
+//! ```
+
+use std::error::Error;
+use std::fmt;
+use std::fs::File;
+use std::io::{self, Read};
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+use toml::{self, Table, Value};
+
+#[cfg(feature = "slack-adapter")]
+use std::env;
+
+use adapter::CliAdapter;
+#[cfg(feature = "irc-adapter")]
+use adapter::{FloodControl, InvitePolicy, IrcAdapter, IrcAuth, IrcConfig};
+#[cfg(feature = "slack-adapter")]
+use adapter::{EventsConfig, SlackAdapter, SlackEventsAdapter};
+use brain::{BrainError, JsonFileBrain};
+use chatbot::Chatbot;
+use handler::FailurePolicy;
+
+/// Failure modes when loading a configuration
+#[derive(Debug)]
+pub enum ConfigError {
+    /// The file could not be read
+    Io(io::Error),
+    /// The file is not valid TOML. Contains a description of each problem.
+    Parse(Vec<String>),
+    /// The TOML is valid but doesn't describe a bot
+    Invalid(String),
+    /// An adapter could not be set up, such as a failed Slack login
+    Adapter(String),
+    /// The brain file could not be opened
+    Brain(BrainError),
+}
+
+impl Error for ConfigError {
+    fn description(&self) -> &str {
+        match *self {
+            ConfigError::Io(ref err) => err.description(),
+            ConfigError::Parse(_) => "Config is not valid TOML",
+            ConfigError::Invalid(_) => "Config is invalid",
+            ConfigError::Adapter(_) => "Failed to set up adapter",
+            ConfigError::Brain(ref err) => err.description(),
+        }
+    }
+
+    fn cause(&self) -> Option<&Error> {
+        match *self {
+            ConfigError::Io(ref err) => Some(err),
+            ConfigError::Brain(ref err) => Some(err),
+            _ => None
+        }
+    }
+}
+
+impl fmt::Display for ConfigError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            ConfigError::Io(ref err) => write!(f, "Io({})", err),
+            ConfigError::Parse(ref errs) => write!(f, "Parse({})", errs.join("; ")),
+            ConfigError::Invalid(ref what) => write!(f, "Invalid({})", what),
+            ConfigError::Adapter(ref what) => write!(f, "Adapter({})", what),
+            ConfigError::Brain(ref err) => write!(f, "Brain({})", err),
+        }
+    }
+}
+
+impl From<io::Error> for ConfigError {
+    fn from(err: io::Error) -> ConfigError {
+        ConfigError::Io(err)
+    }
+}
+
+impl From<BrainError> for ConfigError {
+    fn from(err: BrainError) -> ConfigError {
+        ConfigError::Brain(err)
+    }
+}
+
+/// Settings for one adapter
+#[derive(Debug)]
+pub enum AdapterConfig {
+    Cli,
+    #[cfg(feature = "irc-adapter")]
+    Irc {
+        /// Adapter and network name, when it isn't the default
+        name: Option<String>,
+        config: IrcConfig,
+        flood: FloodControl,
+        /// How to log in, when it isn't with the config's `nick_password`
+        auth: Option<IrcAuth>,
+        invites: InvitePolicy,
+    },
+    #[cfg(feature = "slack-adapter")]
+    Slack {
+        /// API token for the bot user
+        token: String,
+        /// Whether edited messages are handled again
+        edits: bool,
+    },
+    /// Slack's Events API, with `mode = "events"`
+    #[cfg(feature = "slack-adapter")]
+    SlackEvents(EventsConfig),
+}
+
+/// A parsed configuration file
+#[derive(Debug)]
+pub struct Config {
+    pub name: String,
+    pub adapters: Vec<AdapterConfig>,
+    pub help: bool,
+    pub brain_path: Option<PathBuf>,
+    pub handler_timeout: Option<Duration>,
+    pub worker_threads: Option<usize>,
+    /// The adapter and channel told when a handler is disabled
+    pub operator: Option<(String, String)>,
+    pub failure_policy: Option<FailurePolicy>,
+}
+
+/// Load the config at `path` and build a Chatbot from it
+pub fn load<P: AsRef<Path>>(path: P) -> Result<Chatbot, ConfigError> {
+    Config::load(path).and_then(|config| config.build())
+}
+
+impl Config {
+    /// Read and parse the config at `path`
+    pub fn load<P: AsRef<Path>>(path: P) -> Result<Config, ConfigError> {
+        let mut raw = String::new();
+        try!(try!(File::open(path)).read_to_string(&mut raw));
+        Config::parse(&raw)
+    }
+
+    /// Parse a config from a string
+    pub fn parse(raw: &str) -> Result<Config, ConfigError> {
+        let mut parser = toml::Parser::new(raw);
+        let root = match parser.parse() {
+            Some(root) => root,
+            None => {
+                let errors = parser.errors.iter().map(|err| {
+                    let (line, col) = parser.to_linecol(err.lo);
+                    format!("{}:{}: {}", line + 1, col + 1, err.desc)
+                }).collect();
+                return Err(ConfigError::Parse(errors))
+            }
+        };
+
+        let name = match try!(get_str(&root, "name")) {
+            Some(name) => name,
+            None => return Err(ConfigError::Invalid("`name` is required".to_owned()))
+        };
+
+        let mut adapters = Vec::new();
+        for table in try!(get_tables(&root, "adapters")) {
+            adapters.push(try!(parse_adapter(table, &name)));
+        }
+
+        let mut help = false;
+        if let Some(handlers) = try!(get_table(&root, "handlers")) {
+            for (handler, settings) in handlers {
+                let settings = match settings.as_table() {
+                    Some(settings) => settings,
+                    None => return Err(wrong_type(&format!("handlers.{}", handler), "table"))
+                };
+                let enabled = try!(get_bool(settings, "enabled")).unwrap_or(true);
+
+                match handler.as_ref() {
+                    "help" => help = enabled,
+                    _ => {
+                        return Err(ConfigError::Invalid(format!("unknown handler `{}`", handler)))
+                    }
+                }
+            }
+        }
+
+        let brain_path = match try!(get_table(&root, "brain")) {
+            Some(brain) => try!(get_str(brain, "path")).map(PathBuf::from),
+            None => None
+        };
+
+        let failure_policy = match try!(get_table(&root, "failure_policy")) {
+            Some(policy) => {
+                let default = FailurePolicy::default();
+                let max_failures = try!(get_uint(policy, "max_failures")).map(|n| n as usize);
+                let window = try!(get_uint(policy, "window")).map(Duration::from_secs);
+
+                Some(FailurePolicy::new(max_failures.unwrap_or(default.max_failures),
+                                        window.unwrap_or(default.window)))
+            },
+            None => None
+        };
+
+        let operator = match try!(get_table(&root, "operator")) {
+            Some(operator) => {
+                match (try!(get_str(operator, "adapter")), try!(get_str(operator, "channel"))) {
+                    (Some(adapter), Some(channel)) => Some((adapter, channel)),
+                    _ => {
+                        let what = "`operator` needs an `adapter` and a `channel`".to_owned();
+                        return Err(ConfigError::Invalid(what))
+                    }
+                }
+            },
+            None => None
+        };
+
+        Ok(Config {
+            name: name,
+            adapters: adapters,
+            help: help,
+            brain_path: brain_path,
+            handler_timeout: try!(get_uint(&root, "handler_timeout")).map(Duration::from_secs),
+            worker_threads: try!(get_uint(&root, "worker_threads")).map(|n| n as usize),
+            operator: operator,
+            failure_policy: failure_policy,
+        })
+    }
+
+    /// Create the Chatbot described by this config
+    ///
+    /// Adapters which need to log in, such as Slack, do so here so that bad credentials are
+    /// reported as a `ConfigError` rather than a panic.
+    pub fn build(self) -> Result<Chatbot, ConfigError> {
+        let mut bot = Chatbot::new(&self.name);
+
+        for adapter in self.adapters {
+            match adapter {
+                AdapterConfig::Cli => bot.add_adapter(CliAdapter::new(&self.name)),
+                #[cfg(feature = "irc-adapter")]
+                AdapterConfig::Irc { name, config, flood, auth, invites } => {
+                    let mut irc = IrcAdapter::new(config, &self.name)
+                        .with_flood_control(flood)
+                        .with_invites(invites);
+                    if let Some(name) = name {
+                        irc = irc.with_name(&name);
+                    }
+                    if let Some(auth) = auth {
+                        irc = irc.with_auth(auth);
+                    }
+                    bot.add_adapter(irc)
+                },
+                #[cfg(feature = "slack-adapter")]
+                AdapterConfig::Slack { token, edits } => {
+                    match SlackAdapter::login(&self.name, &token) {
+                        Ok(slack) => bot.add_adapter(slack.with_edits(edits)),
+                        Err(e) => {
+                            return Err(ConfigError::Adapter(format!("slack login: {:?}", e)))
+                        }
+                    }
+                },
+                #[cfg(feature = "slack-adapter")]
+                AdapterConfig::SlackEvents(config) => {
+                    match SlackEventsAdapter::start(config) {
+                        Ok(slack) => bot.add_adapter(slack),
+                        Err(e) => {
+                            return Err(ConfigError::Adapter(format!("slack events: {}", e)))
+                        }
+                    }
+                },
+            }
+        }
+
+        if let Some(path) = self.brain_path {
+            bot.set_brain(try!(JsonFileBrain::open(path)));
+        }
+
+        if let Some(timeout) = self.handler_timeout {
+            bot.set_handler_timeout(timeout);
+        }
+
+        if let Some(threads) = self.worker_threads {
+            bot.set_worker_threads(threads);
+        }
+
+        if let Some((adapter, channel)) = self.operator {
+            bot.set_operator_channel(&adapter, &channel);
+        }
+
+        if let Some(policy) = self.failure_policy {
+            bot.set_failure_policy(policy);
+        }
+
+        if self.help {
+            bot.enable_help();
+        }
+
+        Ok(bot)
+    }
+}
+
+fn parse_adapter(table: &Table, bot_name: &str) -> Result<AdapterConfig, ConfigError> {
+    let kind = match try!(get_str(table, "type")) {
+        Some(kind) => kind,
+        None => return Err(ConfigError::Invalid("every adapter needs a `type`".to_owned()))
+    };
+
+    match kind.as_ref() {
+        "cli" => Ok(AdapterConfig::Cli),
+        "irc" => parse_irc(table, bot_name),
+        #[cfg(feature = "slack-adapter")]
+        "slack" => {
+            let token = try!(get_secret(table, "token", "SLACK_BOT_TOKEN"));
+            let edits = try!(get_bool(table, "handle_edits")).unwrap_or(false);
+
+            match try!(get_str(table, "mode")) {
+                None => Ok(AdapterConfig::Slack { token: token, edits: edits }),
+                Some(ref mode) if mode == "rtm" => {
+                    Ok(AdapterConfig::Slack { token: token, edits: edits })
+                },
+                Some(ref mode) if mode == "events" => {
+                    let defaults = EventsConfig::default();
+                    Ok(AdapterConfig::SlackEvents(EventsConfig {
+                        token: token,
+                        signing_secret: try!(get_secret(table, "signing_secret",
+                                                        "SLACK_SIGNING_SECRET")),
+                        listen: try!(get_str(table, "listen")).unwrap_or(defaults.listen),
+                        api_url: try!(get_str(table, "api_url")).unwrap_or(defaults.api_url),
+                        edits: edits,
+                    }))
+                },
+                Some(mode) => {
+                    Err(ConfigError::Invalid(format!("unknown slack mode `{}`", mode)))
+                }
+            }
+        },
+        #[cfg(not(feature = "slack-adapter"))]
+        "slack" => Err(ConfigError::Invalid("the slack adapter requires the slack-adapter feature"
+                                            .to_owned())),
+        _ => Err(ConfigError::Invalid(format!("unknown adapter type `{}`", kind)))
+    }
+}
+
+#[cfg(feature = "irc-adapter")]
+fn parse_irc(table: &Table, bot_name: &str) -> Result<AdapterConfig, ConfigError> {
+    let server = match try!(get_str(table, "server")) {
+        Some(server) => server,
+        None => return Err(ConfigError::Invalid("irc adapter needs a `server`".to_owned()))
+    };
+
+    let mut flood = FloodControl::default();
+    if let Some(burst) = try!(get_uint(table, "flood_burst")) {
+        flood.burst = burst as u32;
+    }
+    if let Some(rate) = try!(get_float(table, "flood_rate")) {
+        flood.per_second = rate;
+    }
+    if flood.burst == 0 || flood.per_second <= 0.0 {
+        return Err(ConfigError::Invalid("irc `flood_burst` and `flood_rate` must be \
+                                         positive".to_owned()));
+    }
+
+    let nickname = try!(get_str(table, "nickname")).unwrap_or(bot_name.to_owned());
+    let auth = match try!(get_str(table, "sasl_password")) {
+        Some(password) => Some(IrcAuth::Sasl {
+            account: try!(get_str(table, "sasl_account")).unwrap_or(nickname.clone()),
+            password: password,
+        }),
+        None => None
+    };
+
+    let invites = match try!(get_str_list(table, "invites_from")) {
+        Some(nicks) => InvitePolicy::From(nicks),
+        None if try!(get_bool(table, "accept_invites")).unwrap_or(false) => {
+            InvitePolicy::Accept
+        },
+        None => InvitePolicy::Ignore,
+    };
+
+    let config = IrcConfig {
+        nickname: Some(nickname),
+        alt_nicks: try!(get_str_list(table, "alt_nicks")),
+        username: try!(get_str(table, "username")),
+        realname: try!(get_str(table, "realname")),
+        server: Some(server),
+        port: try!(get_uint(table, "port")).map(|port| port as u16),
+        password: try!(get_str(table, "password")),
+        nick_password: try!(get_str(table, "nick_password")),
+        use_ssl: try!(get_bool(table, "use_ssl")),
+        channels: try!(get_str_list(table, "channels")),
+        .. Default::default()
+    };
+
+    Ok(AdapterConfig::Irc {
+        name: try!(get_str(table, "name")),
+        config: config,
+        flood: flood,
+        auth: auth,
+        invites: invites,
+    })
+}
+
+#[cfg(not(feature = "irc-adapter"))]
+fn parse_irc(_table: &Table, _bot_name: &str) -> Result<AdapterConfig, ConfigError> {
+    Err(ConfigError::Invalid("the irc adapter requires the irc-adapter feature".to_owned()))
+}
+
+/// A secret given as `key` in the table, or read from the environment variable named by
+/// `{key}_env`, or from `default_var`
+#[cfg(feature = "slack-adapter")]
+fn get_secret(table: &Table, key: &str, default_var: &str) -> Result<String, ConfigError> {
+    if let Some(secret) = try!(get_str(table, key)) {
+        return Ok(secret);
+    }
+
+    let var = try!(get_str(table, &format!("{}_env", key))).unwrap_or(default_var.to_owned());
+    env::var(&var).map_err(|_| {
+        ConfigError::Invalid(format!("slack adapter needs `{}` or ${} to be set", key, var))
+    })
+}
+
+fn wrong_type(key: &str, expected: &str) -> ConfigError {
+    ConfigError::Invalid(format!("`{}` should be a {}", key, expected))
+}
+
+fn get_str(table: &Table, key: &str) -> Result<Option<String>, ConfigError> {
+    match table.get(key) {
+        Some(value) => match value.as_str() {
+            Some(s) => Ok(Some(s.to_owned())),
+            None => Err(wrong_type(key, "string"))
+        },
+        None => Ok(None)
+    }
+}
+
+fn get_bool(table: &Table, key: &str) -> Result<Option<bool>, ConfigError> {
+    match table.get(key) {
+        Some(value) => match value.as_bool() {
+            Some(b) => Ok(Some(b)),
+            None => Err(wrong_type(key, "boolean"))
+        },
+        None => Ok(None)
+    }
+}
+
+fn get_uint(table: &Table, key: &str) -> Result<Option<u64>, ConfigError> {
+    match table.get(key) {
+        Some(value) => match value.as_integer() {
+            Some(n) if n >= 0 => Ok(Some(n as u64)),
+            _ => Err(wrong_type(key, "non-negative integer"))
+        },
+        None => Ok(None)
+    }
+}
+
+#[cfg(feature = "irc-adapter")]
+fn get_float(table: &Table, key: &str) -> Result<Option<f64>, ConfigError> {
+    match table.get(key) {
+        Some(&Value::Float(n)) => Ok(Some(n)),
+        Some(&Value::Integer(n)) => Ok(Some(n as f64)),
+        Some(_) => Err(wrong_type(key, "number")),
+        None => Ok(None)
+    }
+}
+
+#[cfg(feature = "irc-adapter")]
+fn get_str_list(table: &Table, key: &str) -> Result<Option<Vec<String>>, ConfigError> {
+    let values = match table.get(key) {
+        Some(value) => match value.as_slice() {
+            Some(values) => values,
+            None => return Err(wrong_type(key, "list of strings"))
+        },
+        None => return Ok(None)
+    };
+
+    let mut list = Vec::new();
+    for value in values {
+        match value.as_str() {
+            Some(s) => list.push(s.to_owned()),
+            None => return Err(wrong_type(key, "list of strings"))
+        }
+    }
+
+    Ok(Some(list))
+}
+
+fn get_table<'a>(table: &'a Table, key: &str) -> Result<Option<&'a Table>, ConfigError> {
+    match table.get(key) {
+        Some(value) => match value.as_table() {
+            Some(t) => Ok(Some(t)),
+            None => Err(wrong_type(key, "table"))
+        },
+        None => Ok(None)
+    }
+}
+
+fn get_tables<'a>(table: &'a Table, key: &str) -> Result<Vec<&'a Table>, ConfigError> {
+    match table.get(key) {
+        Some(&Value::Array(ref values)) => {
+            values.iter().map(|v| v.as_table().ok_or(wrong_type(key, "list of tables"))).collect()
+        },
+        Some(_) => Err(wrong_type(key, "list of tables")),
+        None => Ok(Vec::new())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use config::{AdapterConfig, Config, ConfigError};
+
+    #[test]
+    fn test_parse_full_config() {
+        let config = Config::parse(r##"
+            name = "testbot"
+            handler_timeout = 10
+            worker_threads = 2
+
+            [failure_policy]
+            max_failures = 3
+
+            [operator]
+            adapter = "cli"
+            channel = "#ops"
+
+            [brain]
+            path = "/tmp/brain.json"
+
+            [handlers.help]
+            enabled = true
+
+            [[adapters]]
+            type = "cli"
+        "##).unwrap();
+
+        assert_eq!(config.name, "testbot");
+        assert!(config.help);
+        assert_eq!(config.handler_timeout, Some(Duration::from_secs(10)));
+        assert_eq!(config.worker_threads, Some(2));
+        assert_eq!(config.operator, Some(("cli".to_owned(), "#ops".to_owned())));
+        assert_eq!(config.failure_policy.unwrap().max_failures, 3);
+        assert_eq!(config.failure_policy.unwrap().window, Duration::from_secs(60));
+        assert_eq!(config.brain_path.unwrap().to_str(), Some("/tmp/brain.json"));
+        match config.adapters[..] {
+            [AdapterConfig::Cli] => (),
+            _ => panic!("expected a single cli adapter")
+        }
+    }
+
+    #[test]
+    fn test_build_minimal_config() {
+        let bot = Config::parse("name = \"testbot\"\n[[adapters]]\ntype = \"cli\"")
+            .and_then(|config| config.build())
+            .unwrap();
+        assert_eq!(bot.get_name(), "testbot");
+    }
+
+    #[test]
+    fn test_invalid_configs() {
+        let invalid = [
+            "[[adapters]]\ntype = \"cli\"",
+            "name = 5",
+            "name = \"bot\"\n[[adapters]]\ntype = \"carrier-pigeon\"",
+            "name = \"bot\"\n[[adapters]]\nserver = \"irc.mozilla.org\"",
+            "name = \"bot\"\n[handlers.karma]\nenabled = true",
+            "name = \"bot\"\n[operator]\nchannel = \"#ops\"",
+            "name = \"bot\"\nworker_threads = -1",
+        ];
+
+        for raw in invalid.iter() {
+            match Config::parse(raw) {
+                Err(ConfigError::Invalid(_)) => (),
+                other => panic!("expected Invalid for {:?}, got {:?}", raw, other)
+            }
+        }
+    }
+
+    #[test]
+    fn test_parse_error_has_position() {
+        match Config::parse("name = \"bot\"\nthis is not toml") {
+            Err(ConfigError::Parse(errors)) => assert!(errors[0].starts_with("2:")),
+            other => panic!("expected Parse, got {:?}", other)
+        }
+    }
+}
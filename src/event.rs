@@ -0,0 +1,213 @@
+//! Types for things that happen in a chat other than messages, such as users joining channels or
+//! topics changing.
+//!
+//! Adapters which can report these send an [`IncomingEvent`](struct.IncomingEvent.html) for each
+//! one. They are delivered to the [`EventHandler`](../handler/trait.EventHandler.html)s which
+//! subscribed to the event's [`EventKind`](enum.EventKind.html).
+
+use std::fmt;
+use std::fmt::Debug;
+use std::fmt::Formatter;
+use std::sync::mpsc::Sender;
+use std::sync::mpsc::SendError;
+
+use brain::Namespace;
+use message::AdapterMsg;
+use message::Target;
+
+/// The kinds of `Event`, used by handlers to subscribe to them
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum EventKind {
+    Join,
+    Part,
+    Quit,
+    Nick,
+    Topic,
+    Kick,
+}
+
+/// A change in who is in a channel, who someone is, or what a channel is about
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Event {
+    /// `user` joined `channel`
+    Join {
+        channel: String,
+        user: String,
+    },
+    /// `user` left `channel`
+    Part {
+        channel: String,
+        user: String,
+        reason: Option<String>,
+    },
+    /// `user` disconnected from the service
+    Quit {
+        user: String,
+        reason: Option<String>,
+    },
+    /// The user known as `old` is now known as `new`
+    Nick {
+        old: String,
+        new: String,
+    },
+    /// The topic of `channel` was set, by `user` when that is known
+    Topic {
+        channel: String,
+        user: Option<String>,
+        topic: String,
+    },
+    /// `user` was removed from `channel`, by `by` when that is known
+    Kick {
+        channel: String,
+        user: String,
+        by: Option<String>,
+        reason: Option<String>,
+    },
+}
+
+impl Event {
+    pub fn kind(&self) -> EventKind {
+        match *self {
+            Event::Join { .. } => EventKind::Join,
+            Event::Part { .. } => EventKind::Part,
+            Event::Quit { .. } => EventKind::Quit,
+            Event::Nick { .. } => EventKind::Nick,
+            Event::Topic { .. } => EventKind::Topic,
+            Event::Kick { .. } => EventKind::Kick,
+        }
+    }
+
+    /// The channel the event happened in. `Quit` and `Nick` aren't tied to a channel.
+    pub fn channel(&self) -> Option<&str> {
+        match *self {
+            Event::Join { ref channel, .. } |
+            Event::Part { ref channel, .. } |
+            Event::Topic { ref channel, .. } |
+            Event::Kick { ref channel, .. } => Some(channel),
+            Event::Quit { .. } | Event::Nick { .. } => None,
+        }
+    }
+
+    /// The user the event is about. For `Nick` this is the new name, and for `Kick` the user who
+    /// was removed.
+    pub fn user(&self) -> Option<&str> {
+        match *self {
+            Event::Join { ref user, .. } |
+            Event::Part { ref user, .. } |
+            Event::Quit { ref user, .. } |
+            Event::Kick { ref user, .. } => Some(user),
+            Event::Nick { ref new, .. } => Some(new),
+            Event::Topic { ref user, .. } => user.as_ref().map(|user| user.as_ref()),
+        }
+    }
+}
+
+/// An `Event` as produced by an adapter, along with what handlers need to respond to it
+#[derive(Clone)]
+pub struct IncomingEvent {
+    event: Event,
+    from_adapter: String,
+    server: Option<String>,
+    brain: Option<Namespace>,
+    tx: Sender<AdapterMsg>,
+}
+
+impl IncomingEvent {
+    pub fn new(from_adapter: String, server: Option<String>, event: Event,
+               sender: Sender<AdapterMsg>) -> IncomingEvent {
+        IncomingEvent {
+            event: event,
+            from_adapter: from_adapter,
+            server: server,
+            brain: None,
+            tx: sender,
+        }
+    }
+
+    /// Name of the adapter which produced this event
+    pub fn from_adapter(&self) -> &str {
+        self.from_adapter.as_ref()
+    }
+
+    pub fn server(&self) -> Option<&str> {
+        self.server.as_ref().map(|server| server.as_ref())
+    }
+
+    pub fn event(&self) -> &Event {
+        &self.event
+    }
+
+    pub fn kind(&self) -> EventKind {
+        self.event.kind()
+    }
+
+    /// The handler's namespace in the bot's [`Brain`](../brain/trait.Brain.html), attached by the
+    /// Chatbot before calling a handler
+    pub fn brain(&self) -> Option<&Namespace> {
+        self.brain.as_ref()
+    }
+
+    /// A copy of this event carrying `brain` for the handler about to receive it
+    pub(crate) fn with_brain(&self, brain: Namespace) -> IncomingEvent {
+        IncomingEvent {
+            brain: Some(brain),
+            .. self.clone()
+        }
+    }
+
+    /// Send `msg` to `target` through the adapter which produced this event
+    pub fn send(&self, target: Target, msg: String) -> Result<(), SendError<AdapterMsg>> {
+        self.tx.send(AdapterMsg::Proactive(target, msg))
+    }
+
+    /// Send `msg` to the channel the event happened in.
+    ///
+    /// `Quit` and `Nick` aren't tied to a channel, so for them `msg` goes privately to the user
+    /// the event is about, by their new name for `Nick`. A user who quit has usually left the
+    /// service, so a reply to `Quit` may go nowhere. Use [`send`](#method.send) to choose where a
+    /// message goes instead.
+    pub fn reply(&self, msg: String) -> Result<(), SendError<AdapterMsg>> {
+        let target = match (self.event.channel(), self.event.user()) {
+            (Some(channel), _) => Target::Channel(channel.to_owned()),
+            (None, Some(user)) => Target::User(user.to_owned()),
+            (None, None) => unreachable!("every event has a channel or a user"),
+        };
+
+        self.send(target, msg)
+    }
+}
+
+impl Debug for IncomingEvent {
+    fn fmt(&self, f: &mut Formatter) -> Result<(), fmt::Error> {
+        write!(f, "IncomingEvent(from_adapter: {:?}, server: {:?}, event: {:?})",
+               self.from_adapter, self.server, self.event)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::mpsc::channel;
+
+    use event::{Event, EventKind, IncomingEvent};
+    use message::{AdapterMsg, Target};
+
+    #[test]
+    fn test_reply_goes_to_channel_or_user() {
+        let (tx, rx) = channel();
+        let join = Event::Join { channel: "#chan".to_owned(), user: "joe".to_owned() };
+        let quit = Event::Quit { user: "joe".to_owned(), reason: None };
+        assert_eq!(quit.kind(), EventKind::Quit);
+
+        IncomingEvent::new("test".to_owned(), None, join, tx.clone())
+            .reply("hi".to_owned()).unwrap();
+        IncomingEvent::new("test".to_owned(), None, quit, tx).reply("bye".to_owned()).unwrap();
+
+        let targets = rx.iter().map(|msg| match msg {
+            AdapterMsg::Proactive(target, _) => target,
+            _ => unreachable!()
+        }).collect::<Vec<_>>();
+
+        assert_eq!(targets, vec![Target::Channel("#chan".to_owned()),
+                                 Target::User("joe".to_owned())]);
+    }
+}
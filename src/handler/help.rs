@@ -0,0 +1,187 @@
+use regex::Regex;
+
+use handler::HandlerResult;
+use handler::MessageHandler;
+use message::IncomingMessage;
+
+/// What the help handler knows about another handler
+struct Topic {
+    name: String,
+    description: Option<String>,
+    usage: Option<String>,
+    addressed: bool,
+}
+
+/// Lists the bot's handlers using their `description` and `usage` metadata.
+///
+/// `help` lists every handler and `help <name>` shows the details for one of them. Handlers which
+/// only respond when the bot is addressed are marked as such. The Chatbot registers this handler
+/// as an addressed handler when [`enable_help`](../struct.Chatbot.html#method.enable_help) is
+/// called, so there is usually no need to build one by hand.
+pub struct HelpHandler {
+    regex: Regex,
+    topics: Vec<Topic>,
+}
+
+impl HelpHandler {
+    pub fn new() -> HelpHandler {
+        let mut help = HelpHandler {
+            regex: regex!(r"^help(?:\s+(?P<topic>\S+))?\s*$"),
+            topics: Vec::new(),
+        };
+
+        help.topics.push(Topic {
+            name: "help".to_owned(),
+            description: help.description().map(|d| d.to_owned()),
+            usage: help.usage().map(|u| u.to_owned()),
+            addressed: true,
+        });
+
+        help
+    }
+
+    /// Describe `handler` in the help output. `addressed` should be true if the handler only
+    /// receives messages which address the bot.
+    pub fn add(&mut self, handler: &MessageHandler, addressed: bool) {
+        self.topics.push(Topic {
+            name: handler.name().to_owned(),
+            description: handler.description().map(|d| d.to_owned()),
+            usage: handler.usage().map(|u| u.to_owned()),
+            addressed: addressed,
+        });
+    }
+
+    /// The lines sent in reply to a plain `help`
+    fn summary(&self) -> Vec<String> {
+        let mut lines = vec!["Available commands:".to_owned()];
+
+        for topic in &self.topics {
+            let addressed = if topic.addressed { " (addressed)" } else { "" };
+            let line = match topic.description {
+                Some(ref description) => format!("  {}{} - {}", topic.name, addressed, description),
+                None => format!("  {}{}", topic.name, addressed),
+            };
+            lines.push(line);
+        }
+
+        lines.push("Addressed commands need the bot's name first. \
+                    Use `help <name>` for details.".to_owned());
+        lines
+    }
+
+    /// The lines sent in reply to `help <name>`
+    fn details(&self, name: &str) -> Vec<String> {
+        let name = name.to_lowercase();
+        let topic = match self.topics.iter().find(|t| t.name.to_lowercase() == name) {
+            Some(topic) => topic,
+            None => return vec![format!("No command named `{}`. Try `help`.", name)]
+        };
+
+        let mut lines = vec![match topic.description {
+            Some(ref description) => format!("{} - {}", topic.name, description),
+            None => topic.name.clone(),
+        }];
+
+        if let Some(ref usage) = topic.usage {
+            lines.extend(usage.lines().map(|line| format!("  {}", line)));
+        }
+
+        if topic.addressed {
+            lines.push("Only responds when the bot is addressed by name.".to_owned());
+        }
+
+        lines
+    }
+}
+
+impl MessageHandler for HelpHandler {
+    fn name(&self) -> &str {
+        "help"
+    }
+
+    fn re(&self) -> &Regex {
+        &self.regex
+    }
+
+    fn description(&self) -> Option<&str> {
+        Some("Lists the commands the bot understands")
+    }
+
+    fn usage(&self) -> Option<&str> {
+        Some("help - list all commands\nhelp <name> - show details for one command")
+    }
+
+    fn handle(&self, incoming: &IncomingMessage) -> HandlerResult {
+        let lines = match self.get_captures(incoming.get_contents())
+                              .and_then(|caps| caps.name("topic").map(|t| t.to_owned())) {
+            Some(topic) => self.details(&topic),
+            None => self.summary(),
+        };
+
+        // Sent line by line since not every adapter can deliver multi-line messages
+        for line in lines {
+            try!(incoming.reply(line));
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::mpsc::channel;
+
+    use handler::HelpHandler;
+    use handler::MessageHandler;
+    use message::AdapterMsg;
+    use message::IncomingMessage;
+
+    fn ask(help: &HelpHandler, question: &str) -> Vec<String> {
+        assert!(help.can_handle(question));
+        let (tx, rx) = channel();
+        let msg = IncomingMessage::new("test".to_owned(), None, None, None,
+                                       question.to_owned(), tx);
+        help.handle(&msg).unwrap();
+        drop(msg);
+
+        rx.iter().map(|m| match m {
+            AdapterMsg::Outgoing(out) => out.as_ref().to_owned(),
+            _ => unreachable!()
+        }).collect()
+    }
+
+    fn help() -> HelpHandler {
+        let mut help = HelpHandler::new();
+        let ping = handler!("Ping", r"ping", |_, _| Some("pong".to_owned()),
+                            description: "Checks that the bot is alive");
+        let slap = handler!("TroutSlap", r"slap (?P<user>.+)", |_, _| None,
+                            description: "Slaps a user", usage: "slap <user>");
+        help.add(&ping, false);
+        help.add(&slap, true);
+        help
+    }
+
+    #[test]
+    fn test_help_lists_handlers() {
+        let lines = ask(&help(), "help");
+        assert!(lines.contains(&"  Ping - Checks that the bot is alive".to_owned()));
+        assert!(lines.contains(&"  TroutSlap (addressed) - Slaps a user".to_owned()));
+    }
+
+    #[test]
+    fn test_help_topic() {
+        let lines = ask(&help(), "help troutslap");
+        assert_eq!(lines[0], "TroutSlap - Slaps a user");
+        assert_eq!(lines[1], "  slap <user>");
+        assert!(lines[2].contains("addressed"));
+
+        let lines = ask(&help(), "help nope");
+        assert!(lines[0].contains("No command named `nope`"));
+    }
+
+    #[test]
+    fn test_help_ignores_other_messages() {
+        assert!(!help().can_handle("helpful"));
+        assert!(!help().can_handle("can you help me"));
+    }
+}
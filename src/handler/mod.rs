@@ -6,17 +6,27 @@ use std::error::Error;
 use std::fmt;
 use std::io;
 use std::sync::mpsc::SendError;
+use std::time::Duration;
 use regex::Regex;
 use regex::Captures;
 
+use event::EventKind;
+use event::IncomingEvent;
 use message::IncomingMessage;
 use message::AdapterMsg;
 
+mod help;
+pub use self::help::HelpHandler;
+
 /// Failure modes for a MessageHandler
 #[derive(Debug)]
 pub enum HandlerError {
     /// Failed to send reply
     Reply(SendError<AdapterMsg>),
+    /// The handler panicked. Contains the panic message when one was available.
+    Panic(String),
+    /// The handler ran longer than its timeout. It is left to finish in the background.
+    Timeout(Duration),
     /// Other indicates any mode that's not explicitly part of HandlerError
     Other(Box<Error>)
 }
@@ -25,6 +35,8 @@ impl Error for HandlerError {
     fn description(&self) -> &str {
         match *self {
             HandlerError::Reply(_) => "Failed to send reply because adapter disconnected",
+            HandlerError::Panic(_) => "Handler panicked",
+            HandlerError::Timeout(_) => "Handler timed out",
             HandlerError::Other(ref err) => err.description()
         }
     }
@@ -32,7 +44,7 @@ impl Error for HandlerError {
     fn cause(&self) -> Option<&Error> {
         match *self {
             HandlerError::Reply(ref err) => Some(err),
-            HandlerError::Other(_) => None
+            _ => None
         }
     }
 }
@@ -41,6 +53,10 @@ impl fmt::Display for HandlerError {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
         match *self {
             HandlerError::Reply(ref err) => write!(f, "Reply error: {}", err),
+            HandlerError::Panic(ref msg) => write!(f, "Panic: {}", msg),
+            HandlerError::Timeout(ref d) => {
+                write!(f, "Timed out after {}.{:03}s", d.as_secs(), d.subsec_nanos() / 1_000_000)
+            },
             HandlerError::Other(ref err) => write!(f, "Other error: {}", err),
         }
     }
@@ -60,6 +76,40 @@ impl From<io::Error> for HandlerError {
 
 pub type HandlerResult = Result<(), HandlerError>;
 
+/// Decides when a misbehaving handler is disabled by the Chatbot.
+///
+/// Every error returned from `handle`, and every panic, counts as a failure. Once a handler has
+/// failed `max_failures` times within `window` it stops receiving messages. A `max_failures` of
+/// zero means handlers are never disabled.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct FailurePolicy {
+    /// Number of failures which disables a handler
+    pub max_failures: usize,
+    /// How far back failures are counted
+    pub window: Duration,
+}
+
+impl FailurePolicy {
+    pub fn new(max_failures: usize, window: Duration) -> FailurePolicy {
+        FailurePolicy {
+            max_failures: max_failures,
+            window: window
+        }
+    }
+
+    /// A policy which never disables handlers
+    pub fn never() -> FailurePolicy {
+        FailurePolicy::new(0, Duration::from_secs(0))
+    }
+}
+
+impl Default for FailurePolicy {
+    /// Five failures within one minute
+    fn default() -> FailurePolicy {
+        FailurePolicy::new(5, Duration::from_secs(60))
+    }
+}
+
 /// Implementing a MessageHandler enables responding to IncomingMessages. There
 /// are currently very few requirements to creating a handler. The
 /// [`handle`](#tymethod.handle) function receives
@@ -68,6 +118,10 @@ pub type HandlerResult = Result<(), HandlerError>;
 /// call [`reply`](../message/struct.IncomingMessage.html#method.reply) on the
 /// [`IncomingMessage`](../message/struct.IncomingMessage.html) to send a response.
 ///
+/// Handlers are run on a pool of worker threads and must therefore be `Send + Sync`. A single
+/// handler only ever processes one message at a time, in the order the messages arrived, so its
+/// replies stay in order.
+///
 /// # Example
 ///
 /// A simple echo handler might look something like the following:
@@ -112,11 +166,27 @@ pub type HandlerResult = Result<(), HandlerError>;
 /// # }
 /// ```
 ///
-pub trait MessageHandler {
+pub trait MessageHandler: Send + Sync {
     fn name(&self) -> &str;
     fn handle(&self, incoming: &IncomingMessage) -> HandlerResult;
     fn re(&self) -> &Regex;
 
+    /// How long a single call to `handle` may run before it is reported as a
+    /// `HandlerError::Timeout`. `None` uses the Chatbot's default.
+    fn timeout(&self) -> Option<Duration> {
+        None
+    }
+
+    /// A one line summary of what the handler does, shown by the help handler
+    fn description(&self) -> Option<&str> {
+        None
+    }
+
+    /// How to use the handler, shown by `help <name>`. May span several lines.
+    fn usage(&self) -> Option<&str> {
+        None
+    }
+
     /// Uses re() to test whether the handler should process this message.
     fn can_handle(&self, msg: &str) -> bool {
         self.re().is_match(msg)
@@ -128,26 +198,107 @@ pub trait MessageHandler {
     }
 }
 
+/// Implementing an EventHandler enables reacting to things other than messages, like users
+/// joining a channel or a topic changing. The handler lists the
+/// [`EventKind`](../event/enum.EventKind.html)s it wants in [`kinds`](#tymethod.kinds) and
+/// receives a matching [`IncomingEvent`](../event/struct.IncomingEvent.html) for each one.
+///
+/// Event handlers run on the same worker pool as MessageHandlers, get the same failure handling
+/// and brain namespace, and likewise process one event at a time in the order they arrived.
+///
+/// # Example
+///
+/// A greeter might look something like the following:
+///
+/// ```rust
+/// use chatbot::event::{Event, EventKind, IncomingEvent};
+/// use chatbot::handler::{EventHandler, HandlerResult};
+///
+/// struct Greeter;
+///
+/// impl EventHandler for Greeter {
+///     fn name(&self) -> &str {
+///         "greeter"
+///     }
+///
+///     fn kinds(&self) -> &[EventKind] {
+///         &[EventKind::Join]
+///     }
+///
+///     fn handle(&self, incoming: &IncomingEvent) -> HandlerResult {
+///         if let Event::Join { ref user, .. } = *incoming.event() {
+///             try!(incoming.reply(format!("Welcome, {}!", user)));
+///         }
+///         Ok(())
+///     }
+/// }
+/// ```
+pub trait EventHandler: Send + Sync {
+    fn name(&self) -> &str;
+    fn handle(&self, incoming: &IncomingEvent) -> HandlerResult;
+
+    /// The kinds of event this handler is interested in
+    fn kinds(&self) -> &[EventKind];
+
+    /// How long a single call to `handle` may run before it is reported as a
+    /// `HandlerError::Timeout`. `None` uses the Chatbot's default.
+    fn timeout(&self) -> Option<Duration> {
+        None
+    }
+
+    /// Whether the handler should process events of `kind`
+    fn can_handle(&self, kind: EventKind) -> bool {
+        self.kinds().contains(&kind)
+    }
+}
+
 
 /// A basic response handler
 ///
-/// Provide an re matcher, a name, and a lambda to send simple responses.
+/// Provide an re matcher, a name, and a lambda to send simple responses. A description and usage
+/// for the help handler can be added with [`with_description`](#method.with_description) and
+/// [`with_usage`](#method.with_usage), and [`emote`](#method.emote) sends the responses as
+/// actions.
 pub struct BasicResponseHandler {
     name: String,
     trigger: Regex,
-    responder: Box<Fn(Captures, &str) -> Option<String>>
+    responder: Box<Fn(Captures, &str) -> Option<String> + Send + Sync>,
+    description: Option<String>,
+    usage: Option<String>,
+    action: bool,
 }
 
 impl BasicResponseHandler {
     pub fn new<F>(name: &str, trigger: &str, responder: F) -> BasicResponseHandler
-        where F: Fn(Captures, &str) -> Option<String>  + 'static {
+        where F: Fn(Captures, &str) -> Option<String> + Send + Sync + 'static {
 
         BasicResponseHandler {
             name: name.to_owned(),
             responder: Box::new(responder),
-            trigger: regex!(trigger)
+            trigger: regex!(trigger),
+            description: None,
+            usage: None,
+            action: false,
         }
     }
+
+    /// Set the one line summary shown by the help handler
+    pub fn with_description(mut self, description: &str) -> BasicResponseHandler {
+        self.description = Some(description.to_owned());
+        self
+    }
+
+    /// Set the usage shown by `help <name>`
+    pub fn with_usage(mut self, usage: &str) -> BasicResponseHandler {
+        self.usage = Some(usage.to_owned());
+        self
+    }
+
+    /// Send responses as actions, like IRC's `/me`
+    pub fn emote(mut self) -> BasicResponseHandler {
+        self.action = true;
+        self
+    }
 }
 
 impl MessageHandler for BasicResponseHandler {
@@ -159,11 +310,20 @@ impl MessageHandler for BasicResponseHandler {
         &self.trigger
     }
 
+    fn description(&self) -> Option<&str> {
+        self.description.as_ref().map(|d| d.as_ref())
+    }
+
+    fn usage(&self) -> Option<&str> {
+        self.usage.as_ref().map(|u| u.as_ref())
+    }
+
     fn handle(&self, incoming: &IncomingMessage) -> HandlerResult {
         let ref make_response = self.responder;
         let msg = incoming.get_contents();
 
         match make_response(self.get_captures(msg).unwrap(), msg) {
+            Some(response) if self.action => try!(incoming.reply_action(response)),
             Some(response) => try!(incoming.reply(response)),
             None => ()
         }
@@ -214,4 +374,23 @@ mod tests {
             _ => unreachable!()
         }
     }
+
+    #[test]
+    fn test_basic_responder_emote() {
+        let handler = BasicResponseHandler::new("Wave", r"wave", |_, _| {
+            Some("waves".to_owned())
+        }).emote();
+
+        let (tx, rx) = channel();
+        let msg = IncomingMessage::new(handler.name().to_owned(),
+            None, None, None, "wave".to_owned(), tx);
+        handler.handle(&msg).unwrap();
+        match rx.recv().unwrap() {
+            AdapterMsg::Outgoing(out) => {
+                assert!(out.is_action());
+                assert_eq!(out.as_ref(), "waves");
+            },
+            _ => unreachable!()
+        }
+    }
 }
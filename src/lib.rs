@@ -34,14 +34,28 @@
 //! can then do some work and call `reply` on the incoming message to send its response. The adapter
 //! which created the incoming message will decide how to route the message back to the service.
 //!
-//! Handlers are not sandboxed and can thus bring the bot down in flames if they decide to panic. It
-//! may be worth sandboxing in threads in the future (maybe make handlers `Runnable` and send to a
-//! worker pool). The built in handlers are written with care as to not panic the bot.
+//! To react to something other than a message, like a user joining a channel or a topic change,
+//! implement [`EventHandler`](handler/trait.EventHandler.html) instead. Event handlers subscribe
+//! to [`EventKind`](event/enum.EventKind.html)s and are added with `Chatbot::add_event_handler`.
+//!
+//! Handlers run on a pool of worker threads, so a slow handler does not hold up messages for the
+//! others. A handler which panics, returns an error, or runs past its timeout does not bring the
+//! bot down. The failure is logged with the handler name and the incoming message, and a handler
+//! which keeps failing is disabled according to the bot's
+//! [`FailurePolicy`](handler/struct.FailurePolicy.html). The built in handlers are written with
+//! care as to not panic the bot.
+//!
+//! Handlers which need to remember things can use the bot's [`Brain`](brain/trait.Brain.html)
+//! through `IncomingMessage::brain`. It is in-memory by default; use
+//! [`JsonFileBrain`](brain/struct.JsonFileBrain.html) to keep memories across restarts.
 //!
 //! For very simple handlers, there is a `handler!` macro which lets you pass a regex and a closure
 //! without having to implement the MessageHandler trait. The example below contains an example of
 //! this.
 //!
+//! Handlers can be tested end to end with the [`testing`](testing/index.html) module's
+//! `MockAdapter`, which feeds messages through a real Chatbot and collects its replies.
+//!
 //! ## Chatbot
 //!
 //! The Chatbot is the central data structure of the chatbot platform. It contains a `run` method
@@ -69,7 +83,7 @@
 //! bot.add_handler(echo);
 //! bot.add_adapter(CliAdapter::new("echobot"));
 //!
-//! bot.run();
+//! bot.run().unwrap();
 //! # }
 //! ```
 //!
@@ -94,16 +108,22 @@
 //! bot.add_addressed_handler(ping);
 //! bot.add_adapter(CliAdapter::new("pingbot"));
 //!
-//! bot.run();
+//! bot.run().unwrap();
 //! # }
 //! ```
 //!
 
+extern crate ctrlc;
 extern crate regex;
 extern crate rustc_serialize;
+extern crate toml;
+#[cfg(feature = "slack-adapter")]
+extern crate reqwest;
 #[cfg(feature = "slack-adapter")]
 extern crate slack;
 #[cfg(feature = "irc-adapter")]
+extern crate futures;
+#[cfg(feature = "irc-adapter")]
 extern crate irc;
 
 /// Shorthand for creating a `Regex` as suggested by the regex crate. You probably don't need to
@@ -116,7 +136,8 @@ macro_rules! regex(
 /// The `handler!` macro is shorthand for creating simple chat handlers. It
 /// accepts a name, a string used to build a regex for testing the incoming
 /// message and for collecting captures, and a closure which should return a
-/// `String` to be sent as the outgoing message.
+/// `String` to be sent as the outgoing message. A `description` and `usage`
+/// for the help handler may follow the closure.
 ///
 /// # Examples
 ///
@@ -125,6 +146,10 @@ macro_rules! regex(
 /// # extern crate chatbot;
 /// # fn main() {
 /// let ping = handler!("Ping", r"ping", |_, _| Some("pong".to_owned()) );
+///
+/// let echo = handler!("Echo", r"echo (?P<msg>.+)", |caps, _| {
+///     caps.name("msg").map(|msg| msg.to_owned())
+/// }, description: "Repeats what you say", usage: "echo <message>");
 /// # }
 /// ```
 ///
@@ -132,17 +157,37 @@ macro_rules! regex(
 macro_rules! handler {
     ( $name:expr, $rstr:expr, $lambda:expr ) => {
         $crate::handler::BasicResponseHandler::new($name, $rstr, $lambda)
-    }
+    };
+    ( $name:expr, $rstr:expr, $lambda:expr, description: $desc:expr ) => {
+        $crate::handler::BasicResponseHandler::new($name, $rstr, $lambda)
+            .with_description($desc)
+    };
+    ( $name:expr, $rstr:expr, $lambda:expr, description: $desc:expr, usage: $usage:expr ) => {
+        $crate::handler::BasicResponseHandler::new($name, $rstr, $lambda)
+            .with_description($desc)
+            .with_usage($usage)
+    };
 }
 
 
 pub mod adapter;
+pub mod brain;
+pub mod config;
+pub mod event;
 pub mod handler;
 pub mod message;
+pub mod testing;
 
 mod chatbot;
+mod worker;
+pub use chatbot::BotHandle;
+pub use chatbot::BotSendError;
 pub use chatbot::Chatbot;
+pub use chatbot::RunError;
+pub use chatbot::RunningBot;
+pub use chatbot::ShutdownHandle;
 
+pub use handler::EventHandler;
 pub use handler::HandlerResult;
 pub use handler::MessageHandler;
 pub use message::IncomingMessage;
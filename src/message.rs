@@ -7,6 +7,8 @@ use std::fmt::Formatter;
 use std::sync::mpsc::Sender;
 use std::sync::mpsc::SendError;
 
+use brain::Namespace;
+
 /// Adapters will receive on a port that accepts a stream of `AdapterMsg`. The
 /// Outgoing variant contains a message to return. All other variants are
 /// control messages which a well behaved adapter should handle.
@@ -18,23 +20,90 @@ pub enum AdapterMsg {
     Outgoing(OutgoingMessage),
     /// A message that will be sent to the user in private
     Private(OutgoingMessage),
+    /// A message the bot sends on its own initiative rather than in reply to an IncomingMessage
+    Proactive(Target, String),
+    /// Join the named channel. Adapters which can't join channels while running ignore this.
+    Join(String),
+    /// Leave the named channel. Adapters which can't leave channels while running ignore this.
+    Part(String),
     /// The chatbot is shutting down and the adapters should nicely terminate their connections.
     Shutdown
 }
 
+/// Where a proactive message should be delivered. The names are interpreted by the adapter the
+/// message is sent through, e.g. `#chatbot` on IRC or a channel ID on Slack.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Target {
+    /// A channel or room
+    Channel(String),
+    /// A single user, messaged privately
+    User(String),
+}
+
+/// Whether a reply goes in a thread, for adapters whose service has them
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Threading {
+    /// Reply in the thread the message was sent in, or in the channel if it wasn't in one
+    Auto,
+    /// Reply in the message's thread, starting one from the message if needed
+    Thread,
+    /// Reply in the message's thread like `Thread`, and show the reply in the channel too
+    Broadcast,
+}
+
 /// An OutgoingMessage is a response to some IncomingMessage. It contains a
 /// String and a copy of the IncomingMessage that it is in reply to.
 #[derive(Debug)]
 pub struct OutgoingMessage {
     response: String,
-    incoming: IncomingMessage
+    incoming: IncomingMessage,
+    action: bool,
+    threading: Threading
 }
 
 impl OutgoingMessage {
     pub fn new(response: String, incoming: IncomingMessage) -> OutgoingMessage {
         OutgoingMessage {
             response: response,
-            incoming: incoming
+            incoming: incoming,
+            action: false,
+            threading: Threading::Auto
+        }
+    }
+
+    /// A response which describes something the bot does, like IRC's `/me`
+    pub fn action(response: String, incoming: IncomingMessage) -> OutgoingMessage {
+        OutgoingMessage {
+            action: true,
+            .. OutgoingMessage::new(response, incoming)
+        }
+    }
+
+    /// Whether the response is an action. Adapters should render actions in their service's
+    /// native form, e.g. CTCP ACTION on IRC.
+    pub fn is_action(&self) -> bool {
+        self.action
+    }
+
+    /// The same response, threaded according to `threading`
+    pub fn with_threading(mut self, threading: Threading) -> OutgoingMessage {
+        self.threading = threading;
+        self
+    }
+
+    /// How the response should be threaded
+    pub fn threading(&self) -> Threading {
+        self.threading
+    }
+
+    /// The thread the response belongs in, if any. This is the incoming message's thread, or for
+    /// `Thread` and `Broadcast` a new thread started from the incoming message.
+    pub fn thread(&self) -> Option<&str> {
+        match self.threading {
+            Threading::Auto => self.incoming.thread(),
+            Threading::Thread | Threading::Broadcast => {
+                self.incoming.thread().or(self.incoming.id())
+            },
         }
     }
 
@@ -71,6 +140,13 @@ pub struct IncomingMessage {
     server: Option<String>,
     channel: Option<String>,
     user: Option<String>,
+    channel_name: Option<String>,
+    user_name: Option<String>,
+    id: Option<String>,
+    thread: Option<String>,
+    addressed: Option<String>,
+    action: bool,
+    brain: Option<Namespace>,
     tx: Sender<AdapterMsg>
 }
 
@@ -84,10 +160,28 @@ impl IncomingMessage {
             channel: channel,
             user: user,
             message: message,
+            channel_name: None,
+            user_name: None,
+            id: None,
+            thread: None,
+            addressed: None,
+            action: false,
+            brain: None,
             tx: sender
         }
     }
 
+    /// Name of the adapter which produced this message
+    pub fn from_adapter(&self) -> &str {
+        self.from_adapter.as_ref()
+    }
+
+    /// The server or network the message came from, for adapters which can connect to more than
+    /// one
+    pub fn server(&self) -> Option<&str> {
+        self.server.as_ref().map(|server| server.as_ref())
+    }
+
     pub fn channel(&self) -> Option<&str> {
         self.channel.as_ref().map(|chan| chan.as_ref())
     }
@@ -96,26 +190,151 @@ impl IncomingMessage {
         self.user.as_ref().map(|user| user.as_ref())
     }
 
+    /// A readable name for the channel. Adapters whose `channel` is an opaque ID set it with
+    /// `set_channel_name`; otherwise it is the same as `channel`.
+    pub fn channel_name(&self) -> Option<&str> {
+        self.channel_name.as_ref().map(|chan| chan.as_ref()).or(self.channel())
+    }
+
+    /// Set the channel's readable name
+    pub fn set_channel_name(&mut self, name: Option<String>) {
+        self.channel_name = name;
+    }
+
+    /// The user's display name. Adapters whose `user` is an opaque ID set it with
+    /// `set_user_name`; otherwise it is the same as `user`.
+    pub fn user_name(&self) -> Option<&str> {
+        self.user_name.as_ref().map(|user| user.as_ref()).or(self.user())
+    }
+
+    /// Set the user's display name
+    pub fn set_user_name(&mut self, name: Option<String>) {
+        self.user_name = name;
+    }
+
     pub fn get_contents(&self) -> &str {
         self.message.as_ref()
     }
 
+    /// Whether the user was describing something they did, like IRC's `/me`. The contents don't
+    /// include any of the service's action markup.
+    pub fn is_action(&self) -> bool {
+        self.action
+    }
+
+    /// Mark the message as an action. Adapters call this after stripping the action markup from
+    /// the contents.
+    pub fn set_action(&mut self, action: bool) {
+        self.action = action;
+    }
+
+    /// The service's identifier for the message, such as a Slack timestamp, for adapters which
+    /// have one
+    pub fn id(&self) -> Option<&str> {
+        self.id.as_ref().map(|id| id.as_ref())
+    }
+
+    /// Set the message's identifier
+    pub fn set_id(&mut self, id: Option<String>) {
+        self.id = id;
+    }
+
+    /// The identifier of the thread the message was sent in, if it was in one
+    pub fn thread(&self) -> Option<&str> {
+        self.thread.as_ref().map(|thread| thread.as_ref())
+    }
+
+    /// Set the thread the message was sent in
+    pub fn set_thread(&mut self, thread: Option<String>) {
+        self.thread = thread;
+    }
+
+    /// The contents without the part that addressed the bot, for adapters which decide whether
+    /// the bot was addressed while reading the message
+    pub fn addressed(&self) -> Option<&str> {
+        self.addressed.as_ref().map(|contents| contents.as_ref())
+    }
+
+    /// Record that the message addresses the bot, with `contents` as what remains after removing
+    /// the addressing part
+    pub fn set_addressed(&mut self, contents: Option<String>) {
+        self.addressed = contents;
+    }
+
+    /// The handler's namespace in the bot's [`Brain`](../brain/trait.Brain.html). The Chatbot
+    /// attaches it before calling a handler, so it is only `None` for messages which did not come
+    /// through the Chatbot, such as ones built by hand in tests.
+    pub fn brain(&self) -> Option<&Namespace> {
+        self.brain.as_ref()
+    }
+
+    /// A copy of this message carrying `brain` for the handler about to receive it
+    pub(crate) fn with_brain(&self, brain: Namespace) -> IncomingMessage {
+        IncomingMessage {
+            brain: Some(brain),
+            .. self.clone()
+        }
+    }
+
+    /// A copy of this message with different contents. Used to hand addressed handlers the
+    /// message without the part that addressed the bot.
+    pub(crate) fn with_contents(&self, contents: String) -> IncomingMessage {
+        IncomingMessage {
+            message: contents,
+            .. self.clone()
+        }
+    }
+
     /// Reply to the message.
     pub fn reply(&self, msg: String) -> Result<(), SendError<AdapterMsg>> {
         let outgoing = OutgoingMessage::new(msg, self.to_owned());
         self.tx.send(AdapterMsg::Outgoing(outgoing))
     }
 
+    /// Reply in a thread started from the message, or in the thread it was already in. Adapters
+    /// for services without threads reply as usual.
+    pub fn reply_in_thread(&self, msg: String) -> Result<(), SendError<AdapterMsg>> {
+        let outgoing = OutgoingMessage::new(msg, self.to_owned()).with_threading(Threading::Thread);
+        self.tx.send(AdapterMsg::Outgoing(outgoing))
+    }
+
+    /// Reply in the message's thread like [`reply_in_thread`](#method.reply_in_thread), and show
+    /// the reply in the channel too
+    pub fn reply_in_thread_and_channel(&self, msg: String) -> Result<(), SendError<AdapterMsg>> {
+        let outgoing = OutgoingMessage::new(msg, self.to_owned())
+            .with_threading(Threading::Broadcast);
+        self.tx.send(AdapterMsg::Outgoing(outgoing))
+    }
+
     /// Reply to a message in a private message
     pub fn reply_private(&self, msg: String) -> Result<(), SendError<AdapterMsg>> {
         let outgoing = OutgoingMessage::new(msg, self.to_owned());
         self.tx.send(AdapterMsg::Private(outgoing))
     }
+
+    /// Have the adapter which produced the message join `channel`. Anyone can send the bot a
+    /// message, so handlers should check who asked first.
+    pub fn join(&self, channel: &str) -> Result<(), SendError<AdapterMsg>> {
+        self.tx.send(AdapterMsg::Join(channel.to_owned()))
+    }
+
+    /// Have the adapter which produced the message leave `channel`
+    pub fn part(&self, channel: &str) -> Result<(), SendError<AdapterMsg>> {
+        self.tx.send(AdapterMsg::Part(channel.to_owned()))
+    }
+
+    /// Reply with an action, like IRC's `/me`. `msg` should read as the bot's name followed by
+    /// `msg`, e.g. "slaps joe around a bit".
+    pub fn reply_action(&self, msg: String) -> Result<(), SendError<AdapterMsg>> {
+        let outgoing = OutgoingMessage::action(msg, self.to_owned());
+        self.tx.send(AdapterMsg::Outgoing(outgoing))
+    }
 }
 
 impl Debug for IncomingMessage {
     fn fmt(&self, f: &mut Formatter) -> Result<(), fmt::Error> {
         write!(f, "IncomingMessage(from_adapter: {:?}, server: {:?}, channel: {:?}, user: {:?}, \
-            message: {:?})", self.from_adapter, self.server, self.channel, self.user, self.message)
+            thread: {:?}, message: {:?}, action: {:?})", self.from_adapter, self.server,
+            self.channel, self.user, self.thread, self.message, self.action)
     }
 }
@@ -0,0 +1,501 @@
+//! Helpers for testing handlers against a real Chatbot.
+//!
+//! A [`MockAdapter`](struct.MockAdapter.html) stands in for a chat service. Its
+//! [`MockClient`](struct.MockClient.html) injects messages as any user and channel and collects
+//! whatever the bot sends back, so handlers go through the same routing, including addressed vs.
+//! global dispatch, that they do in production.
+//!
+//! # Examples
+//!
+//! ```
+//! # #[macro_use(handler)]
+//! # extern crate chatbot;
+//! # fn main() {
+//! use chatbot::Chatbot;
+//! use chatbot::testing::{MockAdapter, Reply};
+//!
+//! let mut bot = Chatbot::new("testbot");
+//! let adapter = MockAdapter::new("testbot");
+//! let client = adapter.client();
+//!
+//! bot.add_adapter(adapter);
+//! bot.add_addressed_handler(handler!("Ping", r"ping", |_, _| Some("pong".to_owned())));
+//!
+//! let running = bot.start().unwrap();
+//!
+//! client.send("joe", "#test", "testbot: ping");
+//! assert_eq!(client.expect_reply(), Reply::Message {
+//!     channel: Some("#test".to_owned()),
+//!     text: "pong".to_owned()
+//! });
+//!
+//! // Not addressed, so the handler stays quiet
+//! client.send("joe", "#test", "ping");
+//! client.expect_silence();
+//!
+//! running.stop();
+//! running.join().unwrap();
+//! # }
+//! ```
+
+use std::sync::{Arc, Mutex};
+use std::sync::mpsc::{channel, Receiver, RecvTimeoutError, Sender};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use regex::Regex;
+
+use adapter::ChatAdapter;
+use event::{Event, IncomingEvent};
+use message::{AdapterMsg, IncomingMessage, Target};
+
+/// How long `MockClient` waits for the bot before failing a test
+pub const DEFAULT_TIMEOUT_MS: u64 = 2000;
+
+/// How long `MockClient::expect_silence` listens for an unexpected reply
+pub const SILENCE_MS: u64 = 200;
+
+/// Something the bot sent through a MockAdapter
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Reply {
+    /// A reply to a message, sent to the channel the message came from
+    Message {
+        channel: Option<String>,
+        text: String,
+    },
+    /// A reply describing something the bot does, like IRC's `/me`
+    Action {
+        channel: Option<String>,
+        text: String,
+    },
+    /// A reply sent privately to the user who sent the message
+    Private {
+        user: Option<String>,
+        text: String,
+    },
+    /// A message the bot sent on its own through a `BotHandle`
+    Proactive(Target, String),
+    /// A request to join the channel
+    Join(String),
+    /// A request to leave the channel
+    Part(String),
+}
+
+impl Reply {
+    /// The text of the reply regardless of where it was sent, or the channel for `Join` and
+    /// `Part`
+    pub fn text(&self) -> &str {
+        match *self {
+            Reply::Message { ref text, .. } => text,
+            Reply::Action { ref text, .. } => text,
+            Reply::Private { ref text, .. } => text,
+            Reply::Proactive(_, ref text) => text,
+            Reply::Join(ref channel) | Reply::Part(ref channel) => channel,
+        }
+    }
+}
+
+/// An adapter which is driven by a test instead of a chat service
+pub struct MockAdapter {
+    name: String,
+    addresser: Regex,
+    tx_incoming: Arc<Mutex<Option<Sender<IncomingMessage>>>>,
+    tx_events: Arc<Mutex<Option<Sender<IncomingEvent>>>>,
+    tx_outgoing: Sender<AdapterMsg>,
+    rx_outgoing: Arc<Mutex<Receiver<AdapterMsg>>>,
+}
+
+impl MockAdapter {
+    /// Create an adapter named `mock` which is addressed with `bot_name:`
+    pub fn new(bot_name: &str) -> MockAdapter {
+        MockAdapter::named("mock", bot_name)
+    }
+
+    /// Create an adapter with a specific name, for bots with several mock adapters
+    pub fn named(name: &str, bot_name: &str) -> MockAdapter {
+        let (tx_outgoing, rx_outgoing) = channel();
+
+        MockAdapter {
+            name: name.to_owned(),
+            addresser: Regex::new(format!(r"^{}:", bot_name).as_str()).unwrap(),
+            tx_incoming: Arc::new(Mutex::new(None)),
+            tx_events: Arc::new(Mutex::new(None)),
+            tx_outgoing: tx_outgoing,
+            rx_outgoing: Arc::new(Mutex::new(rx_outgoing)),
+        }
+    }
+
+    /// Get a client for talking to the bot through this adapter. Clients can be taken before or
+    /// after the adapter is added to the bot.
+    pub fn client(&self) -> MockClient {
+        MockClient {
+            adapter: self.name.clone(),
+            tx_incoming: self.tx_incoming.clone(),
+            tx_events: self.tx_events.clone(),
+            tx_outgoing: self.tx_outgoing.clone(),
+            rx_outgoing: self.rx_outgoing.clone(),
+        }
+    }
+}
+
+impl ChatAdapter for MockAdapter {
+    fn get_name(&self) -> &str {
+        self.name.as_ref()
+    }
+
+    fn addresser(&self) -> &Regex {
+        &self.addresser
+    }
+
+    fn process_events(&mut self, tx_incoming: Sender<IncomingMessage>) {
+        *self.tx_incoming.lock().unwrap() = Some(tx_incoming);
+    }
+
+    fn set_event_sender(&mut self, tx_events: Sender<IncomingEvent>) {
+        *self.tx_events.lock().unwrap() = Some(tx_events);
+    }
+
+    fn outgoing(&self) -> Option<Sender<AdapterMsg>> {
+        Some(self.tx_outgoing.clone())
+    }
+
+    fn shutdown(&mut self) {
+        self.tx_incoming.lock().unwrap().take();
+        self.tx_events.lock().unwrap().take();
+    }
+}
+
+/// The test's end of a MockAdapter
+#[derive(Clone)]
+pub struct MockClient {
+    adapter: String,
+    tx_incoming: Arc<Mutex<Option<Sender<IncomingMessage>>>>,
+    tx_events: Arc<Mutex<Option<Sender<IncomingEvent>>>>,
+    tx_outgoing: Sender<AdapterMsg>,
+    rx_outgoing: Arc<Mutex<Receiver<AdapterMsg>>>,
+}
+
+impl MockClient {
+    /// Send `text` to the bot as `user` in `channel`
+    ///
+    /// Waits for the bot to start if it hasn't yet. Panics if it doesn't start within
+    /// `DEFAULT_TIMEOUT_MS` or has stopped.
+    pub fn send(&self, user: &str, channel: &str, text: &str) {
+        self.send_message(user, channel, text, false);
+    }
+
+    /// Send `text` to the bot as an action by `user` in `channel`, like IRC's `/me`
+    pub fn send_action(&self, user: &str, channel: &str, text: &str) {
+        self.send_message(user, channel, text, true);
+    }
+
+    fn send_message(&self, user: &str, channel: &str, text: &str, action: bool) {
+        let mut msg = IncomingMessage::new(self.adapter.clone(), None, Some(channel.to_owned()),
+                                           Some(user.to_owned()), text.to_owned(),
+                                           self.tx_outgoing.clone());
+        msg.set_action(action);
+        self.wait_started();
+
+        match *self.tx_incoming.lock().unwrap() {
+            Some(ref tx) => tx.send(msg).ok().expect("chatbot is no longer running"),
+            None => panic!("chatbot is no longer running"),
+        }
+    }
+
+    /// Report `event` to the bot
+    ///
+    /// Waits for the bot to start like `send`. Panics if the bot has no event handlers, since it
+    /// doesn't ask adapters for events then.
+    pub fn send_event(&self, event: Event) {
+        let event = IncomingEvent::new(self.adapter.clone(), None, event,
+                                       self.tx_outgoing.clone());
+        self.wait_started();
+
+        match *self.tx_events.lock().unwrap() {
+            Some(ref tx) => tx.send(event).ok().expect("chatbot is no longer running"),
+            None => panic!("chatbot has no event handlers or is no longer running"),
+        }
+    }
+
+    /// Wait for the bot to call `process_events` on the adapter
+    fn wait_started(&self) {
+        let start = Instant::now();
+
+        while self.tx_incoming.lock().unwrap().is_none() {
+            if start.elapsed() > Duration::from_millis(DEFAULT_TIMEOUT_MS) {
+                panic!("chatbot did not start within {}ms", DEFAULT_TIMEOUT_MS);
+            }
+
+            thread::sleep(Duration::from_millis(10));
+        }
+    }
+
+    /// Wait up to `timeout` for the bot to send something
+    pub fn recv(&self, timeout: Duration) -> Option<Reply> {
+        let rx = self.rx_outgoing.lock().unwrap();
+        let deadline = Instant::now() + timeout;
+
+        loop {
+            let remaining = deadline.saturating_duration_since(Instant::now());
+            let msg = match rx.recv_timeout(remaining) {
+                Ok(msg) => msg,
+                Err(RecvTimeoutError::Timeout) => return None,
+                Err(RecvTimeoutError::Disconnected) => return None,
+            };
+
+            return Some(match msg {
+                AdapterMsg::Outgoing(ref m) if m.is_action() => Reply::Action {
+                    channel: m.get_incoming().channel().map(|c| c.to_owned()),
+                    text: m.as_ref().to_owned(),
+                },
+                AdapterMsg::Outgoing(m) => Reply::Message {
+                    channel: m.get_incoming().channel().map(|c| c.to_owned()),
+                    text: m.as_ref().to_owned(),
+                },
+                AdapterMsg::Private(m) => Reply::Private {
+                    user: m.get_incoming().user().map(|u| u.to_owned()),
+                    text: m.as_ref().to_owned(),
+                },
+                AdapterMsg::Proactive(target, text) => Reply::Proactive(target, text),
+                AdapterMsg::Join(channel) => Reply::Join(channel),
+                AdapterMsg::Part(channel) => Reply::Part(channel),
+                // Control messages aren't replies
+                _ => continue,
+            });
+        }
+    }
+
+    /// Get the next reply, panicking if none arrives within `DEFAULT_TIMEOUT_MS`
+    pub fn expect_reply(&self) -> Reply {
+        match self.recv(Duration::from_millis(DEFAULT_TIMEOUT_MS)) {
+            Some(reply) => reply,
+            None => panic!("expected a reply within {}ms", DEFAULT_TIMEOUT_MS)
+        }
+    }
+
+    /// Panic if the bot sends anything within `SILENCE_MS`
+    pub fn expect_silence(&self) {
+        if let Some(reply) = self.recv(Duration::from_millis(SILENCE_MS)) {
+            panic!("expected no reply, got {:?}", reply);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use regex::{Captures, Regex};
+
+    use chatbot::Chatbot;
+    use handler::{HandlerResult, MessageHandler};
+    use message::{IncomingMessage, Target};
+    use testing::{MockAdapter, Reply};
+
+    /// A handler which passes matching messages and their captures to `respond`, for tests which
+    /// need more of the message than `handler!` gives
+    struct Respond<F> {
+        name: &'static str,
+        regex: Regex,
+        respond: F,
+    }
+
+    impl<F> MessageHandler for Respond<F>
+        where F: Fn(Captures, &IncomingMessage) -> HandlerResult + Send + Sync
+    {
+        fn name(&self) -> &str {
+            self.name
+        }
+
+        fn re(&self) -> &Regex {
+            &self.regex
+        }
+
+        fn handle(&self, incoming: &IncomingMessage) -> HandlerResult {
+            let caps = self.regex.captures(incoming.get_contents()).unwrap();
+            (self.respond)(caps, incoming)
+        }
+    }
+
+    fn respond<F>(name: &'static str, pattern: &str, respond: F) -> Respond<F>
+        where F: Fn(Captures, &IncomingMessage) -> HandlerResult + Send + Sync
+    {
+        Respond {
+            name: name,
+            regex: Regex::new(pattern).unwrap(),
+            respond: respond,
+        }
+    }
+
+    #[test]
+    fn test_full_bot_routing() {
+        let mut bot = Chatbot::new("testbot");
+        let adapter = MockAdapter::new("testbot");
+        let client = adapter.client();
+        bot.add_adapter(adapter);
+
+        bot.add_handler(handler!("Echo", r"^echo (?P<msg>.+)", |caps, _| {
+            caps.name("msg").map(|msg| msg.to_owned())
+        }));
+        bot.add_addressed_handler(handler!("Slap", r"^slap (?P<user>.+)", |caps, _| {
+            caps.name("user").map(|user| format!("slaps {}", user))
+        }));
+
+        let handle = bot.handle();
+        let running = bot.start().unwrap();
+
+        client.send("joe", "#test", "echo hello");
+        assert_eq!(client.expect_reply(), Reply::Message {
+            channel: Some("#test".to_owned()),
+            text: "hello".to_owned()
+        });
+
+        client.send("joe", "#test", "slap bob");
+        client.expect_silence();
+
+        // The address is stripped before the handler sees the message
+        client.send("joe", "#test", "testbot: slap bob");
+        assert_eq!(client.expect_reply().text(), "slaps bob");
+
+        handle.send("mock", Target::User("joe".to_owned()), "psst").unwrap();
+        assert_eq!(client.expect_reply(),
+                   Reply::Proactive(Target::User("joe".to_owned()), "psst".to_owned()));
+
+        running.stop();
+        running.join().unwrap();
+    }
+
+    #[test]
+    fn test_actions() {
+        let mut bot = Chatbot::new("testbot");
+        let adapter = MockAdapter::new("testbot");
+        let client = adapter.client();
+        bot.add_adapter(adapter);
+        bot.add_handler(respond("Hug", r"^hugs testbot$", |_, incoming| {
+            if incoming.is_action() {
+                let user = incoming.user().unwrap();
+                try!(incoming.reply_action(format!("hugs {} back", user)));
+            }
+            Ok(())
+        }));
+        bot.add_handler(handler!("Wave", r"^wave$", |_, _| Some("waves".to_owned())).emote());
+
+        let running = bot.start().unwrap();
+
+        client.send("joe", "#test", "hugs testbot");
+        client.expect_silence();
+
+        client.send_action("joe", "#test", "hugs testbot");
+        assert_eq!(client.expect_reply(), Reply::Action {
+            channel: Some("#test".to_owned()),
+            text: "hugs joe back".to_owned()
+        });
+
+        client.send("joe", "#test", "wave");
+        assert_eq!(client.expect_reply(), Reply::Action {
+            channel: Some("#test".to_owned()),
+            text: "waves".to_owned()
+        });
+
+        running.stop();
+        running.join().unwrap();
+    }
+
+    #[test]
+    fn test_event_handler() {
+        use event::{Event, EventKind, IncomingEvent};
+        use handler::{EventHandler, HandlerResult};
+
+        struct Greeter;
+
+        impl EventHandler for Greeter {
+            fn name(&self) -> &str {
+                "Greeter"
+            }
+
+            fn kinds(&self) -> &[EventKind] {
+                &[EventKind::Join]
+            }
+
+            fn handle(&self, incoming: &IncomingEvent) -> HandlerResult {
+                let user = incoming.event().user().unwrap().to_owned();
+                Ok(try!(incoming.reply(format!("Welcome, {}!", user))))
+            }
+        }
+
+        let mut bot = Chatbot::new("testbot");
+        let adapter = MockAdapter::new("testbot");
+        let client = adapter.client();
+        bot.add_adapter(adapter);
+        bot.add_event_handler(Greeter);
+
+        let running = bot.start().unwrap();
+
+        client.send_event(Event::Part {
+            channel: "#test".to_owned(),
+            user: "bob".to_owned(),
+            reason: None
+        });
+        client.expect_silence();
+
+        client.send_event(Event::Join { channel: "#test".to_owned(), user: "joe".to_owned() });
+        let welcome = "Welcome, joe!".to_owned();
+        assert_eq!(client.expect_reply(),
+                   Reply::Proactive(Target::Channel("#test".to_owned()), welcome));
+
+        running.stop();
+        running.join().unwrap();
+    }
+
+    #[test]
+    fn test_private_reply() {
+        let mut bot = Chatbot::new("testbot");
+        let adapter = MockAdapter::new("testbot");
+        let client = adapter.client();
+        bot.add_adapter(adapter);
+        bot.add_handler(respond("Whisper", r"^whisper$", |_, incoming| {
+            Ok(try!(incoming.reply_private("a secret".to_owned())))
+        }));
+
+        let running = bot.start().unwrap();
+
+        client.send("joe", "#test", "whisper");
+        assert_eq!(client.expect_reply(), Reply::Private {
+            user: Some("joe".to_owned()),
+            text: "a secret".to_owned()
+        });
+
+        running.stop();
+        running.join().unwrap();
+    }
+
+    #[test]
+    fn test_join_and_part() {
+        let mut bot = Chatbot::new("testbot");
+        let adapter = MockAdapter::new("testbot");
+        let client = adapter.client();
+        bot.add_adapter(adapter);
+        let pattern = r"^(?P<command>join|part) (?P<channel>#\S+)$";
+        bot.add_handler(respond("Channels", pattern, |caps, incoming| {
+            if incoming.user() != Some("admin") {
+                return Ok(());
+            }
+
+            let channel = caps.name("channel").unwrap();
+            match caps.name("command") {
+                Some("join") => Ok(try!(incoming.join(channel))),
+                _ => Ok(try!(incoming.part(channel))),
+            }
+        }));
+
+        let running = bot.start().unwrap();
+
+        client.send("joe", "#test", "join #rust");
+        client.expect_silence();
+        client.send("admin", "#test", "join #rust");
+        assert_eq!(client.expect_reply(), Reply::Join("#rust".to_owned()));
+        client.send("admin", "#test", "part #test");
+        assert_eq!(client.expect_reply(), Reply::Part("#test".to_owned()));
+
+        running.stop();
+        running.join().unwrap();
+    }
+}
@@ -0,0 +1,87 @@
+//! A fixed size pool of threads used to run handlers off of the main loop.
+
+use std::sync::{Arc, Mutex};
+use std::sync::mpsc::{channel, Receiver, Sender};
+use std::thread;
+
+type Job = Box<FnOnce() + Send + 'static>;
+
+/// Runs jobs on a bounded number of threads. Jobs are started in the order they are submitted.
+/// Clones submit to the same threads. Dropping every clone lets the workers exit once the queue is
+/// drained; it does not wait for them.
+#[derive(Clone)]
+pub struct WorkerPool {
+    tx: Sender<Job>,
+}
+
+impl WorkerPool {
+    /// Create a pool with `size` worker threads. At least one thread is always created.
+    pub fn new(size: usize) -> WorkerPool {
+        let (tx, rx) = channel::<Job>();
+        let rx = Arc::new(Mutex::new(rx));
+
+        for i in 0..::std::cmp::max(size, 1) {
+            let rx = rx.clone();
+            thread::Builder::new().name(format!("Chatbot Worker {}", i)).spawn(move || {
+                work(rx);
+            }).ok().expect("failed to create worker thread");
+        }
+
+        WorkerPool {
+            tx: tx
+        }
+    }
+
+    /// Queue a job to run on the next free worker
+    pub fn execute<F>(&self, job: F)
+        where F: FnOnce() + Send + 'static
+    {
+        self.tx.send(Box::new(job)).ok().expect("worker threads exited");
+    }
+}
+
+fn work(rx: Arc<Mutex<Receiver<Job>>>) {
+    loop {
+        let job = {
+            let rx = rx.lock().unwrap();
+            match rx.recv() {
+                Ok(job) => job,
+                Err(_) => break
+            }
+        };
+
+        job();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::mpsc::channel;
+    use std::time::Duration;
+
+    use worker::WorkerPool;
+
+    #[test]
+    fn test_jobs_run_concurrently() {
+        let pool = WorkerPool::new(2);
+        let (tx, rx) = channel();
+        let (release_tx, release_rx) = channel::<()>();
+
+        // The first job blocks until the second one has run
+        {
+            let tx = tx.clone();
+            pool.execute(move || {
+                release_rx.recv().unwrap();
+                tx.send("first").unwrap();
+            });
+        }
+        pool.execute(move || {
+            tx.send("second").unwrap();
+            release_tx.send(()).unwrap();
+        });
+
+        let timeout = Duration::from_secs(5);
+        assert_eq!(rx.recv_timeout(timeout).unwrap(), "second");
+        assert_eq!(rx.recv_timeout(timeout).unwrap(), "first");
+    }
+}